@@ -0,0 +1,72 @@
+use crate::vector_simd::Vector;
+
+// dark pixels are judged against this luminance so noise in black areas doesn't use up
+// the whole sample budget
+const MIN_LUMINANCE: f32 = 0.1;
+
+/// Keeps sampling a pixel after its `aa_samples` squared samples until the estimated
+/// error of its mean drops below the threshold or it reaches `max_samples`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Standard error of the mean luminance relative to the luminance, at 0.01 a pixel stops
+    /// once its value is known to about one percent.
+    pub threshold: f32,
+    pub max_samples: u32
+}
+
+impl AdaptiveSampling {
+    pub fn new(threshold: f32, max_samples: u32) -> Self {
+        Self {
+            threshold: threshold,
+            max_samples: max_samples
+        }
+    }
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self::new(0.01, 256)
+    }
+}
+
+/// Running mean and variance of the sample luminance of a pixel, using Welford's update.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelVariance {
+    pub(crate) count: u32,
+    pub(crate) mean: f32,
+    pub(crate) m2: f32
+}
+
+impl PixelVariance {
+    pub fn add(&mut self, color: Vector) {
+        let value = luminance(color);
+        if !value.is_finite() {
+            return;
+        }
+
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f32;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f32 {
+        if self.count < 2 {0.0} else {self.m2 / (self.count - 1) as f32}
+    }
+
+    /// Relative standard error of the mean, infinite until there are two samples.
+    pub fn error(&self) -> f32 {
+        if self.count < 2 {
+            return f32::INFINITY;
+        }
+        (self.variance() / self.count as f32).sqrt() / self.mean.max(MIN_LUMINANCE)
+    }
+}
+
+pub fn luminance(color: Vector) -> f32 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
@@ -0,0 +1,285 @@
+use crate::framebuffer::Framebuffer;
+use crate::ray_tracer::CameraSample;
+use crate::scene::SceneData;
+use crate::shading::materials::Material;
+use crate::tonemap::{DisplayTransform, srgb_encode};
+use crate::vector_simd::Vector;
+
+/// Auxiliary per pixel buffers written next to the beauty pass, all taken from the first
+/// surface a camera ray hits. The lighting passes split the beauty pass into parts that add
+/// up to it again, indirect light includes everything after the first bounce. Lighting
+/// passes, normal and albedo are averaged over the pixel samples like the beauty pass, the
+/// others keep the value of the sample closest to the pixel center so edges never mix two
+/// objects or depths.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    /// Distance from the camera along the ray, infinite where nothing was hit.
+    Depth,
+    /// World space shading normal.
+    Normal,
+    /// Surface color of the material including vertex colors.
+    Albedo,
+    /// World space position.
+    Position,
+    /// Index of the scene object plus one, 0 for the background.
+    ObjectId,
+    /// Objects with identical materials share an id, 0 for the background.
+    MaterialId,
+    DirectDiffuse,
+    DirectSpecular,
+    IndirectDiffuse,
+    IndirectSpecular,
+    /// Background color where camera rays miss everything.
+    Background,
+    /// Number of camera samples taken for the pixel, varies with adaptive sampling.
+    SampleCount
+}
+
+pub const AOV_COUNT: usize = 12;
+
+impl Aov {
+    pub const ALL: [Aov; AOV_COUNT] = [
+        Aov::Depth, Aov::Normal, Aov::Albedo, Aov::Position, Aov::ObjectId, Aov::MaterialId,
+        Aov::DirectDiffuse, Aov::DirectSpecular, Aov::IndirectDiffuse, Aov::IndirectSpecular, Aov::Background,
+        Aov::SampleCount
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::DirectSpecular => "direct_specular",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::IndirectSpecular => "indirect_specular",
+            Aov::Background => "background",
+            Aov::SampleCount => "sample_count"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        Aov::ALL.iter().cloned().find(|aov| aov.name() == name.to_ascii_lowercase())
+    }
+
+    /// Channels stored in an exr layer, taken from x, y and z of the buffer in order.
+    pub fn exr_channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::SampleCount => &["count"],
+            _ => &["R", "G", "B"]
+        }
+    }
+
+    /// Lighting passes hold radiance and are tone mapped like the beauty pass.
+    pub fn is_radiance(self) -> bool {
+        matches!(self, Aov::DirectDiffuse | Aov::DirectSpecular | Aov::IndirectDiffuse | Aov::IndirectSpecular | Aov::Background)
+    }
+
+    /// Ids and sample counts hold whole numbers that must be stored exactly.
+    pub fn is_integer(self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId | Aov::SampleCount)
+    }
+
+    pub(crate) fn is_filtered(self) -> bool {
+        self.is_radiance() || self == Aov::Normal || self == Aov::Albedo
+    }
+
+    pub(crate) fn value(self, sample: &CameraSample, material_ids: &[u32]) -> Vector {
+        if let Some(lighting) = sample.lighting {
+            match self {
+                Aov::DirectDiffuse => return lighting.direct_diffuse(),
+                Aov::DirectSpecular => return lighting.direct_specular(),
+                Aov::IndirectDiffuse => return lighting.indirect_diffuse(),
+                Aov::IndirectSpecular => return lighting.indirect_specular(),
+                _ => ()
+            }
+        }
+
+        let hit = match sample.hit {
+            Some(ref hit) => hit,
+            None if self == Aov::Depth => return Vector::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            None if self == Aov::Background => return sample.color,
+            None => return Vector::vec3(0.0, 0.0, 0.0)
+        };
+
+        match self {
+            Aov::Depth => Vector::vec3(hit.distance, hit.distance, hit.distance),
+            Aov::Normal => hit.normal,
+            Aov::Albedo => hit.material.albedo,
+            Aov::Position => hit.position,
+            Aov::ObjectId => {
+                let id = (hit.object_index + 1) as f32;
+                Vector::vec3(id, id, id)
+            },
+            Aov::MaterialId => {
+                let id = material_ids[hit.object_index] as f32;
+                Vector::vec3(id, id, id)
+            },
+            _ => Vector::vec3(0.0, 0.0, 0.0)
+        }
+    }
+
+    /// Converts the buffer into a viewable 8 bit image. Lighting passes go through the
+    /// display transform, depth is shown bright near the camera, normals mapped from
+    /// [-1, 1], positions normalized to the bounds of the visible surfaces, ids as random
+    /// colors and sample counts relative to the highest one.
+    pub fn to_rgb_image(self, framebuffer: &Framebuffer, display: &DisplayTransform) -> image::RgbImage {
+        if self.is_radiance() {
+            return framebuffer.to_rgb_image(display);
+        }
+
+        let hits = framebuffer.pixels().iter().filter(|pixel| pixel.x().is_finite());
+        let max_count = framebuffer.pixels().iter().fold(0.0f32, |max, pixel| max.max(pixel.x()));
+        let max_depth = hits.clone().fold(0.0f32, |max, pixel| max.max(pixel.x()));
+        let (min_position, max_position) = hits.fold((Vector::vec3(f32::MAX, f32::MAX, f32::MAX), Vector::vec3(f32::MIN, f32::MIN, f32::MIN)), |(min, max), pixel| {
+            (Vector::vec3(min.x().min(pixel.x()), min.y().min(pixel.y()), min.z().min(pixel.z())), Vector::vec3(max.x().max(pixel.x()), max.y().max(pixel.y()), max.z().max(pixel.z())))
+        });
+
+        let mut image = image::RgbImage::new(framebuffer.width, framebuffer.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let value = framebuffer.get_pixel(x, y);
+            let (r, g, b) = match self {
+                Aov::Depth => {
+                    let v = if value.x().is_finite() && max_depth > 0.0 {1.0 - value.x() / max_depth} else {0.0};
+                    (v, v, v)
+                },
+                Aov::Normal => (value.x() * 0.5 + 0.5, value.y() * 0.5 + 0.5, value.z() * 0.5 + 0.5),
+                Aov::Albedo => (srgb_encode(value.x()), srgb_encode(value.y()), srgb_encode(value.z())),
+                Aov::Position => {
+                    let normalize = |v: f32, min: f32, max: f32| if max > min {(v - min) / (max - min)} else {0.0};
+                    (normalize(value.x(), min_position.x(), max_position.x()), normalize(value.y(), min_position.y(), max_position.y()), normalize(value.z(), min_position.z(), max_position.z()))
+                },
+                Aov::ObjectId | Aov::MaterialId => id_color(value.x() as u32),
+                Aov::SampleCount => {
+                    let v = if max_count > 0.0 {value.x() / max_count} else {0.0};
+                    (v, v, v)
+                },
+                _ => (0.0, 0.0, 0.0)
+            };
+            *pixel = image::Rgb([to_byte(r), to_byte(g), to_byte(b)]);
+        }
+        image
+    }
+}
+
+// the per light passes aren't an aov of their own since their number depends on the scene
+const LIGHT_PASSES_BIT: u32 = 1 << 31;
+
+/// Set of aovs to render, cheap to copy so it can live in the render settings. It can also
+/// ask for one pass per light holding the direct light of just that light.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AovSet {
+    bits: u32
+}
+
+impl AovSet {
+    pub fn new() -> Self {
+        Self { bits: 0 }
+    }
+
+    pub fn all() -> Self {
+        let mut set = Self::new();
+        for aov in Aov::ALL.iter() {
+            set.insert(*aov);
+        }
+        set.insert_light_passes();
+        set
+    }
+
+    /// Adds an aov by name, `lights` adds the per light passes and `all` everything.
+    /// Returns false for unknown names.
+    pub fn insert_name(&mut self, name: &str) -> bool {
+        match (name, Aov::from_name(name)) {
+            ("all", _) => *self = AovSet::all(),
+            ("lights", _) => self.insert_light_passes(),
+            (_, Some(aov)) => self.insert(aov),
+            _ => return false
+        }
+        true
+    }
+
+    pub fn insert_light_passes(&mut self) {
+        self.bits |= LIGHT_PASSES_BIT;
+    }
+
+    pub fn light_passes(&self) -> bool {
+        self.bits & LIGHT_PASSES_BIT != 0
+    }
+
+    pub fn insert(&mut self, aov: Aov) {
+        self.bits |= 1 << aov as u32;
+    }
+
+    pub fn contains(&self, aov: Aov) -> bool {
+        self.bits & (1 << aov as u32) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    pub(crate) fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub(crate) fn from_bits(bits: u32) -> Self {
+        Self { bits: bits }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Aov> {
+        let set = *self;
+        Aov::ALL.iter().cloned().filter(move |aov| set.contains(*aov))
+    }
+}
+
+/// Material ids for every scene object. Materials are stored by value, so objects get the
+/// same id when their materials are identical.
+pub fn material_ids(scene: &SceneData) -> Vec<u32> {
+    let mut materials: Vec<Material> = Vec::new();
+    let mut ids = Vec::new();
+
+    for object in scene.scene_objects.iter() {
+        let id = match materials.iter().position(|material| same_material(material, &object.material)) {
+            Some(index) => index,
+            None => {
+                materials.push(object.material);
+                materials.len() - 1
+            }
+        };
+        ids.push(id as u32 + 1);
+    }
+
+    ids
+}
+
+fn same_material(a: &Material, b: &Material) -> bool {
+    <[f32; 4]>::from(a.albedo)[..3] == <[f32; 4]>::from(b.albedo)[..3] &&
+    <[f32; 4]>::from(a.specular)[..3] == <[f32; 4]>::from(b.specular)[..3] &&
+    a.roughness == b.roughness &&
+    a.ior == b.ior &&
+    a.transmission == b.transmission &&
+    a.metalicness == b.metalicness
+}
+
+// spreads neighbouring ids over the hue circle, id 0 stays black
+fn id_color(id: u32) -> (f32, f32, f32) {
+    if id == 0 {
+        return (0.0, 0.0, 0.0);
+    }
+
+    let mut hash = id.wrapping_mul(0x9e37_79b9);
+    hash ^= hash >> 16;
+    let channel = |shift: u32| 0.25 + 0.75 * ((hash >> shift) & 0xff) as f32 / 255.0;
+    (channel(0), channel(8), channel(16))
+}
+
+fn to_byte(value: f32) -> u8 {
+    let value = if value > 0.0 {value} else {0.0};
+    (value.min(1.0) * 255.0 + 0.5) as u8
+}
@@ -2,6 +2,8 @@ use crate::geometry::*;
 use crate::scene::*;
 
 use std::{fmt};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 #[derive(Clone, Copy, Debug)]
@@ -9,18 +11,19 @@ struct BVHInfo {
     primitive_number: usize,
     bounding_box: BoundingBox,
     center: [f32; 4],
-    section: usize
+    // estimated cost of intersecting the primitive, in triangle tests
+    cost: f32
 }
 
 impl BVHInfo {
-    fn new(primitive_number: usize, bounding_box: BoundingBox) -> Self {
+    fn new(primitive_number: usize, bounding_box: BoundingBox, cost: f32) -> Self {
         let center = bounding_box.bounds[0] * 0.5 + bounding_box.bounds[1] * 0.5;
 
         Self {
             primitive_number: primitive_number,
             bounding_box: bounding_box,
             center: center.into(),
-            section: 0
+            cost: cost
         }
     }
 }
@@ -31,6 +34,44 @@ impl fmt::Display for BVHInfo {
     }
 }
 
+/// Parameters of the binned surface area heuristic builder. Costs are relative, what
+/// matters is the ratio between visiting a node and intersecting a primitive.
+#[derive(Clone, Copy, Debug)]
+pub struct BvhSettings {
+    /// Number of bins the primitive centers are sorted into along the split axis.
+    pub bins: usize,
+    pub traversal_cost: f32,
+    pub intersection_cost: f32,
+    /// Nodes with more primitives are always split, smaller ones only when the heuristic
+    /// finds a split cheaper than a leaf.
+    pub max_leaf_size: usize,
+    /// Threads to build with, 0 for one per cpu.
+    pub threads: usize
+}
+
+impl BvhSettings {
+    pub fn new(bins: usize, traversal_cost: f32, intersection_cost: f32, max_leaf_size: usize) -> Self {
+        Self {
+            bins: bins.max(2),
+            traversal_cost: traversal_cost,
+            intersection_cost: intersection_cost,
+            max_leaf_size: max_leaf_size.max(1),
+            threads: 0
+        }
+    }
+
+    /// Number of threads to build with, the cpu count when `threads` is 0.
+    pub fn thread_count(&self) -> usize {
+        if self.threads == 0 {num_cpus::get()} else {self.threads}
+    }
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        Self::new(12, 0.125, 1.0, 4)
+    }
+}
+
 pub struct BVHBuildNode {
     bounding_box: BoundingBox,
     children:[Box<Option<BVHBuildNode>>; 2],
@@ -86,180 +127,361 @@ impl LinearBVHNode {
 #[derive(Clone, Copy, Debug)]
 struct Section {
     bounding_box: BoundingBox,
-    count: u32
+    count: usize,
+    cost: f32
 }
 
 impl Default for Section {
     fn default() -> Section {
         Section {
             bounding_box: BoundingBox::new(),
-            count: 0
+            count: 0,
+            cost: 0.0
         }
     }
 }
 
-pub fn build_bvh(scene_objects: &[SceneObject]) -> (Vec<LinearBVHNode>, Vec<usize>) {
-    println!("Construct BVH: ");
-    let now = Instant::now();
-   
-    let mut bvh_info_collection = Vec::new();
+/// Deepest a bvh gets, traversal keeps the nodes still to visit on a stack of this size.
+pub const MAX_BVH_DEPTH: usize = 64;
+
+// below these primitive counts starting threads costs more than it saves. Nodes are binned
+// by several threads near the top of big trees, further down whole subtrees are built on
+// threads of their own. Meshes too small for parallel binning get a thread each.
+const PARALLEL_BINNING_MIN: usize = 64 * 1024;
+const PARALLEL_MIN: usize = 4 * 1024;
+
+/// Seconds spent in each phase of a bvh build.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BuildTimes {
+    /// Bounding boxes and centers of the primitives.
+    pub bounds: f64,
+    /// Binning and partitioning into the tree.
+    pub build: f64,
+    /// Flattening the tree into the node array rays traverse.
+    pub flatten: f64
+}
 
-    for i in 0..scene_objects.len() {
-        let bvh_info = BVHInfo::new(i, scene_objects[i].bounding_box);
-        bvh_info_collection.push(bvh_info);
+impl BuildTimes {
+    pub fn total(&self) -> f64 {
+        self.bounds + self.build + self.flatten
     }
-    
-    let mut total_nodes = 0;
-    let mut ordered_scene_object = Vec::new();
-    let root = recursive_build_nodes(& mut bvh_info_collection, 0, scene_objects.len(), &mut total_nodes, scene_objects, & mut ordered_scene_object);
+}
 
-    let mut nodes = vec![LinearBVHNode::new(BoundingBox::new()); total_nodes];
-    flatten_bvh_tree(&root, &mut 0, &mut nodes);
+impl fmt::Display for BuildTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3}ms (bounds {:.3}ms, build {:.3}ms, flatten {:.3}ms)", self.total() * 1e3, self.bounds * 1e3, self.build * 1e3, self.flatten * 1e3)
+    }
+}
 
-    let end = now.elapsed().as_secs() as f64 + now.elapsed().subsec_nanos() as f64 * 1e-9;
-    println!("Created BVH in: {}", end);
-    println!("Nodes created: {}", nodes.len());
+/// How the bvhs of a scene were built.
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    /// Phases of the bvh over the scene objects.
+    pub times: BuildTimes,
+    /// Shape and cost of the bvh over the scene objects.
+    pub report: BvhReport,
+    /// The triangle bvhs built for the meshes of the scene, a mesh shared by several
+    /// objects is built once.
+    pub meshes: Vec<MeshBvhStats>,
+    /// Seconds it took to build all mesh bvhs, several are built at the same time.
+    pub mesh_time: f64
+}
 
-    (nodes, ordered_scene_object)
+#[derive(Clone, Debug, Default)]
+pub struct MeshBvhStats {
+    /// Name of the mesh resource, empty for meshes made in code.
+    pub name: String,
+    pub triangles: usize,
+    pub times: BuildTimes
 }
 
-fn recursive_build_nodes(bvh_info: & mut [BVHInfo], start: usize, end: usize, total_nodes: &mut usize, scene_objects: &[SceneObject], ordered_scene_object: &mut Vec<usize>) -> BVHBuildNode {
-    *total_nodes += 1;
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let triangles: usize = self.meshes.iter().map(|mesh| mesh.triangles).sum();
+
+        writeln!(f, "object bvh built in {}", self.times)?;
+        writeln!(f, "{}", self.report)?;
+        write!(f, "mesh bvhs: {} over {} triangles, built in {:.3}ms", self.meshes.len(), triangles, self.mesh_time * 1e3)?;
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let name = if mesh.name.is_empty() {format!("mesh {}", i)} else {mesh.name.clone()};
+            write!(f, "\n {}: {} triangles, built in {}", name, mesh.triangles, mesh.times)?;
+        }
+        Ok(())
+    }
+}
 
-    let node;
-    let num_objects = end - start;
+pub fn build_bvh(scene_objects: &[SceneObject]) -> (Vec<LinearBVHNode>, Vec<usize>, BvhStats) {
+    build_bvh_with_settings(scene_objects, &BvhSettings::default())
+}
 
-    let mut bounds = BoundingBox::new();
-    for i in start..end {
-        bounds = bounds.union(bvh_info[i].bounding_box);
-    }
+/// Builds the triangle bvhs of the meshes that don't have one yet and the bvh over the
+/// objects, all with the same settings.
+pub fn build_bvh_with_settings(scene_objects: &[SceneObject], settings: &BvhSettings) -> (Vec<LinearBVHNode>, Vec<usize>, BvhStats) {
+    let settings = &BvhSettings { threads: settings.thread_count(), ..*settings };
+    let now = Instant::now();
+    let meshes = build_mesh_bvhs(scene_objects, settings);
+    let mesh_time = now.elapsed().as_secs_f64();
+
+    // an object costs about one triangle test for every level of its triangle bvh
+    let object_cost = |i: usize| 1.0 + (scene_objects[i].mesh().num_tris.max(1) as f32).log2();
 
-    if num_objects == 1 {
-        let first_offset = ordered_scene_object.len() as u32;
+    let (nodes, bvh_info, times) = build_nodes(scene_objects.len(), settings, |i| BVHInfo::new(i, scene_objects[i].bounding_box, object_cost(i)));
+
+    let costs: Vec<f32> = bvh_info.iter().map(|info| info.cost).collect();
+    let stats = BvhStats {
+        times: times,
+        report: BvhReport::new(&nodes, &costs, settings),
+        meshes: meshes,
+        mesh_time: mesh_time
+    };
+
+    (nodes, bvh_info.iter().map(|info| info.primitive_number).collect(), stats)
+}
 
-        for i in start..end {
-            let object_index = bvh_info[i].primitive_number;
-            ordered_scene_object.push(object_index);
+// builds the missing triangle bvhs of the meshes the objects use. Big meshes are built one
+// after the other with all threads, the others several at a time with a thread each. The
+// trees don't depend on the number of threads either way.
+fn build_mesh_bvhs(scene_objects: &[SceneObject], settings: &BvhSettings) -> Vec<MeshBvhStats> {
+    let mut seen = HashSet::new();
+    let resources: Vec<&MeshResource> = scene_objects.iter()
+        .map(|object| &*object.resource)
+        .filter(|resource| resource.bvh.get().is_none() && seen.insert(*resource as *const MeshResource))
+        .collect();
+
+    let (large, small): (Vec<&MeshResource>, Vec<&MeshResource>) = resources.iter().partition(|resource| resource.mesh.indices.len() / 3 >= PARALLEL_BINNING_MIN);
+    for resource in large {
+        resource.bvh.get_or_init(|| build_mesh_bvh_with_settings(&resource.mesh, settings));
+    }
+
+    let single_thread = BvhSettings { threads: 1, ..*settings };
+    let next = AtomicUsize::new(0);
+    crossbeam_utils::thread::scope(|s| {
+        for _ in 0..settings.threads.min(small.len()) {
+            s.spawn(|_| {
+                while let Some(resource) = small.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    resource.bvh.get_or_init(|| build_mesh_bvh_with_settings(&resource.mesh, &single_thread));
+                }
+            });
         }
+    }).unwrap();
 
-        node = BVHBuildNode::leaf_node(first_offset, num_objects as u32, bounds);
-        return node;
-    } else {
-        let mut center_bounds = BoundingBox::new();
-        for i in start..end {
-            center_bounds = center_bounds.union_from_vector(bvh_info[i].center.into());
+    resources.iter().map(|resource| {
+        let bvh = resource.bvh();
+        MeshBvhStats { name: resource.name.clone(), triangles: bvh.triangles.len(), times: bvh.build_times }
+    }).collect()
+}
+
+/// Bvh over the triangles of one mesh. The bvh over the scene objects is the top level, once
+/// a ray reaches an object it continues down the object's triangle bvh.
+pub struct MeshBvh {
+    pub nodes: Vec<LinearBVHNode>,
+    /// Triangle numbers in leaf order, leaves reference a range of them.
+    pub triangles: Vec<u32>,
+    pub build_times: BuildTimes
+}
+
+pub fn build_mesh_bvh(mesh: &Mesh) -> MeshBvh {
+    build_mesh_bvh_with_settings(mesh, &BvhSettings::default())
+}
+
+pub fn build_mesh_bvh_with_settings(mesh: &Mesh, settings: &BvhSettings) -> MeshBvh {
+    let (nodes, bvh_info, times) = build_nodes(mesh.indices.len() / 3, settings, |i| {
+        let mut bounding_box = BoundingBox::new();
+        for index in mesh.indices[i * 3..i * 3 + 3].iter() {
+            bounding_box.extend_bounds(mesh.vertices[*index as usize].pos);
         }
+        BVHInfo::new(i, bounding_box, 1.0)
+    });
 
-        let dim = center_bounds.maximum_extent();
+    MeshBvh {
+        nodes: nodes,
+        triangles: bvh_info.iter().map(|info| info.primitive_number as u32).collect(),
+        build_times: times
+    }
+}
 
-        let mut mid = (start + end) / 2;
+// builds the flattened bvh over `count` primitives and returns them in leaf order
+fn build_nodes<F>(count: usize, settings: &BvhSettings, primitive: F) -> (Vec<LinearBVHNode>, Vec<BVHInfo>, BuildTimes)
+    where F: Fn(usize) -> BVHInfo + Sync
+{
+    let threads = settings.thread_count();
+    let mut times = BuildTimes::default();
 
-        let min: [f32; 4] =  center_bounds.bounds[0].into();  
-        let max: [f32; 4] =  center_bounds.bounds[1].into(); 
-        
-        if min[dim as usize] == max[dim as usize] {
-            let first_offset = ordered_scene_object.len() as u32;
+    let now = Instant::now();
+    let chunks = parallel_chunks(count, if count >= PARALLEL_MIN {threads} else {1}, |range| range.map(&primitive).collect::<Vec<BVHInfo>>());
+    let mut bvh_info: Vec<BVHInfo> = chunks.into_iter().flatten().collect();
+    times.bounds = now.elapsed().as_secs_f64();
 
-            for i in start..end {
-                let object_index = bvh_info[i].primitive_number;
-                ordered_scene_object.push(object_index);
-            }
-    
-            node = BVHBuildNode::leaf_node(first_offset, num_objects as u32, bounds);
-            return node;
-        } else {
-            if num_objects <= 4 {
-                //equal partitation
-                bvh_info[start..end].sort_unstable_by(|a, b| a.center[dim as usize].partial_cmp(&b.center[dim as usize]).unwrap());
-            } else {
-                //SAH
-                let num_sections = 12;
-                let mut sections = vec![Section {..Default::default()}; num_sections];
-
-                //divide in equal size sections
-                for i in start..end {
-                    let a: [f32; 4] = center_bounds.offset(bvh_info[i].center.into()).into();
-                    let mut b = num_sections * a[dim as usize] as usize;
-
-                    if b == num_sections {
-                        b = num_sections - 1;
-                    }
-
-                    // println!("num tris: {}", scene_objects[bvh_info[i].primitive_number].mesh.num_tris);
-                    bvh_info[i].section = b;
-                    sections[b].count += scene_objects[bvh_info[i].primitive_number].mesh.num_tris;
-                    sections[b].bounding_box = sections[b].bounding_box.union(bvh_info[i].bounding_box);
-                }
+    let now = Instant::now();
+    // median splits need up to ceil(log2(count)) levels, below this depth the nodes are
+    // split at the median instead of by the sah so the tree stays within MAX_BVH_DEPTH
+    let max_sah_depth = MAX_BVH_DEPTH.saturating_sub(count.next_power_of_two().trailing_zeros() as usize);
+    let (root, total_nodes) = recursive_build_nodes(&mut bvh_info, 0, 0, max_sah_depth, threads, settings);
+    times.build = now.elapsed().as_secs_f64();
+
+    let now = Instant::now();
+    let mut nodes = vec![LinearBVHNode::new(BoundingBox::new()); total_nodes];
+    flatten_bvh_tree(&root, &mut 0, &mut nodes);
+    times.flatten = now.elapsed().as_secs_f64();
 
-                let mut cost = vec![0.0; num_sections - 1];
+    (nodes, bvh_info, times)
+}
 
-                //estimate cost 
-                //TODO calculate cost based on object now it's 1
-                for i in 0..(num_sections - 1) {
-                    let mut b0 = BoundingBox::new();
-                    let mut b1 = BoundingBox::new();
+// splits 0..count into one range per thread, runs `f` on each and returns the results in order
+fn parallel_chunks<T, F>(count: usize, threads: usize, f: F) -> Vec<T>
+    where T: Send, F: Fn(std::ops::Range<usize>) -> T + Sync
+{
+    if threads <= 1 {
+        return vec![f(0..count)];
+    }
 
-                    let mut count0 = 0;
-                    let mut count1 = 0;
+    let chunk_size = count.div_ceil(threads).max(1);
+    crossbeam_utils::thread::scope(|s| {
+        let f = &f;
+        let handles: Vec<_> = (0..count).step_by(chunk_size)
+            .map(|start| s.spawn(move |_| f(start..(start + chunk_size).min(count))))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    }).unwrap()
+}
 
-                    for j in 0..(i + 1) {
-                        b0 = b0.union(sections[j].bounding_box);
-                        count0 = sections[j].count;
-                    }
+// builds the subtree over `bvh_info`, which starts at `offset` among all primitives. The
+// primitives are partitioned in place, so they end up in leaf order and every leaf refers
+// to the range it covers. Returns the node and the number of nodes in the subtree.
+fn recursive_build_nodes(bvh_info: &mut [BVHInfo], offset: usize, depth: usize, max_sah_depth: usize, threads: usize, settings: &BvhSettings) -> (BVHBuildNode, usize) {
+    let num_objects = bvh_info.len();
+    let binning_threads = if num_objects >= PARALLEL_BINNING_MIN {threads} else {1};
 
-                    for j in i..(num_sections - 1) {
-                        b1 = b1.union(sections[j].bounding_box);
-                        count1 = sections[j].count;
-                    }
+    let (bounds, center_bounds, total_cost) = parallel_chunks(num_objects, binning_threads, |range| node_bounds(&bvh_info[range]))
+        .into_iter()
+        .fold((BoundingBox::new(), BoundingBox::new(), 0.0), |a, b| (a.0.union(b.0), a.1.union(b.1), a.2 + b.2));
 
-                    cost[i] = 0.125 + (count0 as f32 * b0.surface_area() + count1 as f32 * b1.surface_area()) / bounds.surface_area();
-                }
+    if num_objects <= 1 {
+        return (create_leaf(offset, num_objects, bounds), 1);
+    }
 
-                //find cheapest split
-                let mut min_cost = cost[0];
-                let mut min_cost_split_at = 0;
-                for i in 1..(num_sections - 1) {
-                    if cost[i] < min_cost {
-                        min_cost = cost[i];
-                        min_cost_split_at = i;
-                    }
-                }
+    let dim = center_bounds.maximum_extent() as usize;
+    let min: [f32; 4] = center_bounds.min().into();
+    let max: [f32; 4] = center_bounds.max().into();
 
-                let mut leaf_cost = 0;
+    let mid = if min[dim] == max[dim] || depth >= max_sah_depth {
+        // all centers in one spot leave nothing to bin, the primitives are only split by
+        // count when there are too many for a leaf
+        if num_objects <= settings.max_leaf_size {
+            return (create_leaf(offset, num_objects, bounds), 1);
+        }
 
-                for i in start..end {
-                    leaf_cost += scene_objects[i].mesh.num_tris;
-                }
+        let mid = num_objects / 2;
+        bvh_info.select_nth_unstable_by(mid, |a, b| a.center[dim].partial_cmp(&b.center[dim]).unwrap());
+        mid
+    } else {
+        let num_sections = settings.bins;
+
+        let mut sections = vec![Section::default(); num_sections];
+        for chunk in parallel_chunks(num_objects, binning_threads, |range| bin(&bvh_info[range], center_bounds, dim, num_sections)) {
+            for (section, other) in sections.iter_mut().zip(chunk.iter()) {
+                section.count += other.count;
+                section.cost += other.cost;
+                section.bounding_box = section.bounding_box.union(other.bounding_box);
+            }
+        }
 
-                if num_objects > 225 || min_cost < leaf_cost as f32 {
-                    bvh_info[start..end].sort_unstable_by(|a, b| a.section.cmp(&b.section));
+        // sweep from the right once to get the bounds and costs right of every split,
+        // then from the left to evaluate the splits
+        let mut right = vec![Section::default(); num_sections];
+        let mut accumulated = Section::default();
+        for i in (1..num_sections).rev() {
+            accumulated.count += sections[i].count;
+            accumulated.cost += sections[i].cost;
+            accumulated.bounding_box = accumulated.bounding_box.union(sections[i].bounding_box);
+            right[i] = accumulated;
+        }
 
-                    for i in start..end {
-                        if bvh_info[i].section <= min_cost_split_at {
-                            mid = i;
-                        }
-                    }
-                } else {
-                    let first_offset = ordered_scene_object.len() as u32;
+        let inv_area = 1.0 / bounds.surface_area().max(f32::MIN_POSITIVE);
+        let mut left = Section::default();
+        let mut min_cost = f32::INFINITY;
+        let mut min_cost_split_at = 0;
 
-                    for i in start..end {
-                        let object_index = bvh_info[i].primitive_number;
-                        ordered_scene_object.push(object_index);
-                    }
+        // a split after section i puts sections 0..=i on the left
+        for i in 0..(num_sections - 1) {
+            left.count += sections[i].count;
+            left.cost += sections[i].cost;
+            left.bounding_box = left.bounding_box.union(sections[i].bounding_box);
 
-                    node = BVHBuildNode::leaf_node(first_offset, num_objects as u32, bounds);
-                    return node;
-                }
-            }          
-            
-            node = BVHBuildNode::interior_node(
-                    dim as i8,
-                    recursive_build_nodes(bvh_info, start, mid, total_nodes, scene_objects, ordered_scene_object), 
-                    recursive_build_nodes(bvh_info, mid, end, total_nodes, scene_objects, ordered_scene_object)
-                );
-            return node;
+            let right = right[i + 1];
+            if left.count == 0 || right.count == 0 {
+                continue;
+            }
+
+            let cost = settings.traversal_cost + settings.intersection_cost
+                * (left.cost * left.bounding_box.surface_area() + right.cost * right.bounding_box.surface_area()) * inv_area;
+            if cost < min_cost {
+                min_cost = cost;
+                min_cost_split_at = i;
+            }
         }
+
+        let leaf_cost = settings.intersection_cost * total_cost;
+        if num_objects <= settings.max_leaf_size && min_cost >= leaf_cost {
+            return (create_leaf(offset, num_objects, bounds), 1);
+        }
+
+        let mut mid = 0;
+        for i in 0..num_objects {
+            if section_of(&bvh_info[i], center_bounds, dim, num_sections) <= min_cost_split_at {
+                bvh_info.swap(i, mid);
+                mid += 1;
+            }
+        }
+        mid
+    };
+
+    let (left, right) = bvh_info.split_at_mut(mid);
+    let ((left_node, left_nodes), (right_node, right_nodes)) = if threads > 1 && num_objects >= PARALLEL_MIN {
+        let left_threads = threads / 2;
+        crossbeam_utils::thread::scope(|s| {
+            let handle = s.spawn(|_| recursive_build_nodes(left, offset, depth + 1, max_sah_depth, left_threads, settings));
+            let right = recursive_build_nodes(right, offset + mid, depth + 1, max_sah_depth, threads - left_threads, settings);
+            (handle.join().unwrap(), right)
+        }).unwrap()
+    } else {
+        (recursive_build_nodes(left, offset, depth + 1, max_sah_depth, threads, settings), recursive_build_nodes(right, offset + mid, depth + 1, max_sah_depth, threads, settings))
+    };
+
+    (BVHBuildNode::interior_node(dim as i8, left_node, right_node), left_nodes + right_nodes + 1)
+}
+
+// bounds, center bounds and total cost of the primitives
+fn node_bounds(bvh_info: &[BVHInfo]) -> (BoundingBox, BoundingBox, f32) {
+    let mut bounds = BoundingBox::new();
+    let mut center_bounds = BoundingBox::new();
+    let mut total_cost = 0.0;
+    for info in bvh_info.iter() {
+        bounds = bounds.union(info.bounding_box);
+        center_bounds = center_bounds.union_from_vector(info.center.into());
+        total_cost += info.cost;
     }
+    (bounds, center_bounds, total_cost)
+}
+
+fn bin(bvh_info: &[BVHInfo], center_bounds: BoundingBox, dim: usize, num_sections: usize) -> Vec<Section> {
+    let mut sections = vec![Section::default(); num_sections];
+    for info in bvh_info.iter() {
+        let section = &mut sections[section_of(info, center_bounds, dim, num_sections)];
+        section.count += 1;
+        section.cost += info.cost;
+        section.bounding_box = section.bounding_box.union(info.bounding_box);
+    }
+    sections
+}
+
+fn section_of(info: &BVHInfo, center_bounds: BoundingBox, dim: usize, num_sections: usize) -> usize {
+    let offset: [f32; 4] = center_bounds.offset(info.center.into()).into();
+    ((num_sections as f32 * offset[dim]) as usize).min(num_sections - 1)
+}
+
+fn create_leaf(offset: usize, num_objects: usize, bounds: BoundingBox) -> BVHBuildNode {
+    BVHBuildNode::leaf_node(offset as u32, num_objects as u32, bounds)
 }
 
 fn flatten_bvh_tree(build_node: &BVHBuildNode, offset: &mut usize, nodes:  &mut [LinearBVHNode]) -> u32 {
@@ -271,28 +493,96 @@ fn flatten_bvh_tree(build_node: &BVHBuildNode, offset: &mut usize, nodes:  &mut
     // let new_offset = *offset;
 
     if build_node.num_objects > 0 {
-        println!("BVH node objects: {}",  build_node.num_objects);
         nodes[current_offset].prim_offset = build_node.first_object_offset as i32;
         nodes[current_offset].num_prim = build_node.num_objects;
     } else {
-        nodes[current_offset].axis = build_node.split_axis as i8;
+        nodes[current_offset].axis = build_node.split_axis;
 
-        match &*build_node.children[0] {
-            Some(node) => {
-                flatten_bvh_tree(&node, offset, nodes);
-            },
-            None => ()
+        if let Some(node) = &*build_node.children[0] {
+            flatten_bvh_tree(node, offset, nodes);
         }
 
-        match &*build_node.children[1] {
-            Some(node) => {
-                let second_child_offset = flatten_bvh_tree(&node, offset, nodes) as i32;
-                nodes[current_offset].second_child_offset = second_child_offset;
-            },
-            None => ()
-        }   
+        if let Some(node) = &*build_node.children[1] {
+            let second_child_offset = flatten_bvh_tree(node, offset, nodes) as i32;
+            nodes[current_offset].second_child_offset = second_child_offset;
+        }
     }
 
 
     current_offset as u32
-}
\ No newline at end of file
+}
+/// Quality of a built bvh.
+#[derive(Clone, Debug, Default)]
+pub struct BvhReport {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub average_leaf_depth: f32,
+    /// Expected cost of a ray through the root by the surface area heuristic, the cost
+    /// the builder minimizes.
+    pub sah_cost: f32,
+    /// Number of leaves by the number of primitives they hold.
+    pub leaf_sizes: Vec<usize>
+}
+
+impl BvhReport {
+    /// `costs` are the intersection costs of the primitives in leaf order.
+    pub fn new(nodes: &[LinearBVHNode], costs: &[f32], settings: &BvhSettings) -> Self {
+        let mut report = BvhReport {
+            nodes: nodes.len(),
+            leaves: 0,
+            max_depth: 0,
+            average_leaf_depth: 0.0,
+            sah_cost: 0.0,
+            leaf_sizes: Vec::new()
+        };
+
+        if nodes.is_empty() {
+            return report;
+        }
+
+        let inv_root_area = 1.0 / nodes[0].bounding_box.surface_area().max(f32::MIN_POSITIVE);
+        let mut leaf_depths = 0;
+        let mut to_visit = vec![(0, 0)];
+
+        while let Some((index, depth)) = to_visit.pop() {
+            let node = &nodes[index];
+            let area = node.bounding_box.surface_area() * inv_root_area;
+            report.max_depth = report.max_depth.max(depth);
+
+            if node.num_prim > 0 {
+                let start = node.prim_offset as usize;
+                let cost: f32 = costs[start..start + node.num_prim as usize].iter().sum();
+                report.sah_cost += settings.intersection_cost * cost * area;
+
+                report.leaves += 1;
+                leaf_depths += depth;
+                if report.leaf_sizes.len() <= node.num_prim as usize {
+                    report.leaf_sizes.resize(node.num_prim as usize + 1, 0);
+                }
+                report.leaf_sizes[node.num_prim as usize] += 1;
+            } else if node.second_child_offset >= 0 {
+                report.sah_cost += settings.traversal_cost * area;
+                to_visit.push((index + 1, depth + 1));
+                to_visit.push((node.second_child_offset as usize, depth + 1));
+            }
+        }
+
+        report.average_leaf_depth = leaf_depths as f32 / report.leaves.max(1) as f32;
+        report
+    }
+}
+
+impl fmt::Display for BvhReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sizes: Vec<String> = self.leaf_sizes.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(size, count)| format!("{}: {}", size, count))
+            .collect();
+
+        write!(f,
+            "BVH SAH cost: {},\n nodes: {},\n leaves: {},\n max depth: {},\n average leaf depth: {},\n leaves by size: {}",
+            self.sah_cost, self.nodes, self.leaves, self.max_depth, self.average_leaf_depth, sizes.join(", ")
+        )
+    }
+}
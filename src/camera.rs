@@ -1,34 +1,245 @@
 use crate::Vector;
 use crate::matrix::Matrix;
+use crate::math::{clamp, degree_to_radians};
+use crate::shading::monte_carlo::{sample_disk_concentric, sample_regular_polygon};
 
-struct Perspective {
-    fov: f32
+use std::f32::consts;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Perspective {
+    /// Vertical field of view in radians.
+    pub fov: f32
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Orthographic {
+    /// Width of the view in world units, the height follows from the aspect ratio.
+    pub ortho_width: f32
 }
 
-struct Orthographic {
-    ortho_width: f32
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance from the image center is proportional to the angle from the view direction.
+    Equidistant,
+    /// Equal areas on the image cover equal solid angles.
+    Equisolid
 }
 
-enum ProjectionType {
+#[derive(Clone, Copy, Debug)]
+pub struct Fisheye {
+    /// Field of view across the image circle in radians, can be more than 180 degrees.
+    pub fov: f32,
+    pub mapping: FisheyeMapping
+}
+
+/// The panoramic projections ignore the lens. An equirectangular image covers 360 by 180
+/// degrees and should have a 2:1 aspect ratio. A cube map lays out six 90 degree faces in
+/// a 3:2 image, +x -x +y on the top row and -y +z -z on the bottom row, in camera space
+/// where the camera looks down -z. Each face is what a 90 degree camera looking along that
+/// axis sees, with +y up on the side faces and -z at the bottom of the +y face.
+#[derive(Clone, Copy, Debug)]
+pub enum ProjectionType {
     Perspective(Perspective),
-    Orthographic(Orthographic)
+    Orthographic(Orthographic),
+    Equirectangular,
+    Fisheye(Fisheye),
+    CubeMap
+}
+
+/// Thin lens used for depth of field. Without a lens the camera is a pinhole and
+/// everything is in focus.
+#[derive(Clone, Copy, Debug)]
+pub struct Lens {
+    /// Radius of the aperture in world units.
+    pub aperture_radius: f32,
+    /// Distance to the plane in focus, `None` focuses on the camera target.
+    pub focal_distance: Option<f32>,
+    /// Number of aperture blades, less than 3 gives a round aperture and round bokeh.
+    pub blades: u32,
+    /// Rotation of the aperture blades in radians.
+    pub blade_rotation: f32
+}
+
+impl Lens {
+    pub fn new(aperture_radius: f32) -> Self {
+        Self {
+            aperture_radius: aperture_radius,
+            focal_distance: None,
+            blades: 0,
+            blade_rotation: 0.0
+        }
+    }
+
+    /// Creates a lens from an f-number, assuming scene units are meters and a 35mm film
+    /// whose 24mm height is covered by the vertical fov.
+    pub fn from_f_stop(f_stop: f32, fov: f32) -> Self {
+        let focal_length = 0.024 / (2.0 * (fov * 0.5).tan());
+        Self::new(focal_length / (2.0 * f_stop))
+    }
+
+    // point on the aperture in camera space
+    fn sample_aperture(&self, lens_sample: (f32, f32)) -> Vector {
+        let (x, y) = if self.blades >= 3 {
+            sample_regular_polygon(lens_sample.0, lens_sample.1, self.blades, self.blade_rotation)
+        } else {
+            sample_disk_concentric(lens_sample.0, lens_sample.1)
+        };
+
+        Vector::vec3(x * self.aperture_radius, y * self.aperture_radius, 0.0)
+    }
 }
 
 pub struct Camera {
     pub position: Vector,
     pub target: Vector,
+    pub projection: ProjectionType,
+    pub lens: Option<Lens>,
     pub to_world: Matrix
 }
 
 impl Camera {
     pub fn new(position: Vector, target: Vector) -> Self {
+        Self::with_fov(position, target, degree_to_radians(40.0))
+    }
+
+    pub fn with_fov(position: Vector, target: Vector, fov: f32) -> Self {
+        Self::with_projection(position, target, ProjectionType::Perspective(Perspective { fov: fov }))
+    }
+
+    pub fn orthographic(position: Vector, target: Vector, ortho_width: f32) -> Self {
+        Self::with_projection(position, target, ProjectionType::Orthographic(Orthographic { ortho_width: ortho_width }))
+    }
+
+    pub fn with_projection(position: Vector, target: Vector, projection: ProjectionType) -> Self {
         let up = Vector::vec3(0.0, 1.0, 0.0);
         let camera_to_world = Matrix::look_at_rh(position, target, up);
 
         Self {
             position: position,
             target: target,
+            projection: projection,
+            lens: None,
             to_world: camera_to_world
         }
     }
-}
\ No newline at end of file
+
+    pub fn with_lens(mut self, lens: Lens) -> Self {
+        self.lens = Some(lens);
+        self
+    }
+
+    /// Distance to the plane in focus, the target distance unless the lens overrides it.
+    pub fn focal_distance(&self) -> f32 {
+        match self.lens {
+            Some(Lens { focal_distance: Some(distance), .. }) => distance,
+            _ => (self.target - self.position).vec3_length_f32()
+        }
+    }
+
+    /// Returns the origin and normalized direction of the ray through a point on the image.
+    /// `screen_x` and `screen_y` go from -1 to 1, left to right and bottom to top.
+    /// `lens_sample` picks the point on the aperture and is ignored without a lens.
+    /// Points outside the image circle of a fisheye don't generate a ray.
+    pub fn generate_ray(&self, screen_x: f32, screen_y: f32, aspect_ratio: f32, lens_sample: (f32, f32)) -> Option<(Vector, Vector)> {
+        let camera_origin = Vector::vec3(0.0, 0.0, 0.0);
+
+        // camera space, looking down -z
+        let (origin, direction) = match self.projection {
+            ProjectionType::Perspective(perspective) => {
+                let scale = (perspective.fov * 0.5).tan();
+                (camera_origin, Vector::vec3(screen_x * aspect_ratio * scale, screen_y * scale, -1.0))
+            },
+            ProjectionType::Orthographic(orthographic) => {
+                let half_width = orthographic.ortho_width * 0.5;
+                let half_height = half_width / aspect_ratio;
+                (Vector::vec3(screen_x * half_width, screen_y * half_height, 0.0), Vector::vec3(0.0, 0.0, -1.0))
+            },
+            ProjectionType::Equirectangular => {
+                return Some(self.to_world_ray(camera_origin, equirectangular_direction(screen_x, screen_y)));
+            },
+            ProjectionType::Fisheye(fisheye) => {
+                let direction = fisheye_direction(screen_x, screen_y, aspect_ratio, fisheye)?;
+                return Some(self.to_world_ray(camera_origin, direction));
+            },
+            ProjectionType::CubeMap => {
+                return Some(self.to_world_ray(camera_origin, cube_map_direction(screen_x, screen_y)));
+            }
+        };
+
+        // move the origin onto the aperture and aim at the point the pinhole ray hits on the
+        // focal plane, so only points on that plane stay sharp
+        let (origin, direction) = match self.lens {
+            Some(lens) if lens.aperture_radius > 0.0 => {
+                let focus_point = origin + direction * self.focal_distance();
+                let lens_origin = origin + lens.sample_aperture(lens_sample);
+                (lens_origin, focus_point - lens_origin)
+            },
+            _ => (origin, direction)
+        };
+
+        Some(self.to_world_ray(origin, direction))
+    }
+
+    fn to_world_ray(&self, origin: Vector, direction: Vector) -> (Vector, Vector) {
+        let world_origin = origin * self.to_world;
+        let world_direction = (origin + direction) * self.to_world - world_origin;
+        (world_origin, world_direction.vec3_normalize())
+    }
+}
+
+// longitude goes around the full circle from left to right with the view direction in the
+// center, latitude from straight down at the bottom to straight up at the top
+fn equirectangular_direction(screen_x: f32, screen_y: f32) -> Vector {
+    let longitude = screen_x * consts::PI;
+    let latitude = screen_y * consts::FRAC_PI_2;
+    let cos_latitude = latitude.cos();
+
+    Vector::vec3(cos_latitude * longitude.sin(), latitude.sin(), -cos_latitude * longitude.cos())
+}
+
+// the image circle touches the shorter sides of the image
+fn fisheye_direction(screen_x: f32, screen_y: f32, aspect_ratio: f32, fisheye: Fisheye) -> Option<Vector> {
+    let (x, y) = if aspect_ratio >= 1.0 {
+        (screen_x * aspect_ratio, screen_y)
+    } else {
+        (screen_x, screen_y / aspect_ratio)
+    };
+
+    let r = (x * x + y * y).sqrt();
+    if r > 1.0 {
+        return None;
+    }
+
+    let half_fov = fisheye.fov * 0.5;
+    let theta = match fisheye.mapping {
+        FisheyeMapping::Equidistant => r * half_fov,
+        FisheyeMapping::Equisolid => 2.0 * (r * (half_fov * 0.5).sin()).asin()
+    };
+
+    if r == 0.0 {
+        return Some(Vector::vec3(0.0, 0.0, -1.0));
+    }
+
+    let sin_theta = theta.sin();
+    Some(Vector::vec3(sin_theta * x / r, sin_theta * y / r, -theta.cos()))
+}
+
+fn cube_map_direction(screen_x: f32, screen_y: f32) -> Vector {
+    let u = clamp((screen_x + 1.0) * 0.5 * 3.0, 0.0, 2.9999);
+    let v = clamp((1.0 - screen_y) * 0.5 * 2.0, 0.0, 1.9999);
+    let column = u as u32;
+    let row = v as u32;
+
+    // position on the face, s from left to right and t from top to bottom
+    let s = (u - column as f32) * 2.0 - 1.0;
+    let t = (v - row as f32) * 2.0 - 1.0;
+
+    match (row, column) {
+        (0, 0) => Vector::vec3(1.0, -t, s),
+        (0, 1) => Vector::vec3(-1.0, -t, -s),
+        (0, _) => Vector::vec3(s, 1.0, -t),
+        (_, 0) => Vector::vec3(s, -1.0, t),
+        (_, 1) => Vector::vec3(-s, -t, 1.0),
+        (_, _) => Vector::vec3(s, -t, -1.0)
+    }
+}
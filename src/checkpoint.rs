@@ -0,0 +1,347 @@
+use crate::adaptive::{AdaptiveSampling, PixelVariance};
+use crate::aov::{Aov, AovSet};
+use crate::film::FilmData;
+use crate::filter::{FilterType, PixelFilter};
+use crate::framebuffer::Framebuffer;
+use crate::renderer::PixelState;
+use crate::sampler::SamplerType;
+use crate::scene::SceneData;
+use crate::tonemap::{DisplayTransform, ToneMapOperator};
+use crate::vector_simd::Vector;
+use crate::RenderSettings;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::fmt;
+
+// the version is part of the magic, older checkpoints are rejected instead of misread
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+
+const SAMPLERS: [SamplerType; 5] = [SamplerType::Random, SamplerType::Stratified, SamplerType::Halton, SamplerType::Sobol, SamplerType::BlueNoise];
+const FILTERS: [FilterType; 5] = [FilterType::Box, FilterType::Tent, FilterType::Gaussian, FilterType::Mitchell, FilterType::BlackmanHarris];
+const OPERATORS: [ToneMapOperator; 4] = [ToneMapOperator::Clamp, ToneMapOperator::Reinhard, ToneMapOperator::AcesFitted, ToneMapOperator::Filmic];
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// The file is not a checkpoint or is damaged.
+    Format(String),
+    /// The checkpoint doesn't belong to the scene or settings it is resumed with.
+    Mismatch(String)
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(error) => write!(f, "{}", error),
+            CheckpointError::Format(message) => write!(f, "invalid checkpoint: {}", message),
+            CheckpointError::Mismatch(message) => write!(f, "checkpoint doesn't match the render: {}", message)
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(error: io::Error) -> Self {
+        CheckpointError::Io(error)
+    }
+}
+
+/// State of a progressive render between two passes: the filter weighted sums of the
+/// film, the sample count and variance of every pixel, the unfiltered aovs, the settings and
+/// the fingerprint of the scene.
+/// The sampler needs no state of its own, its values only depend on the seed, the pixel and
+/// the sample index, so a resumed render continues with exactly the samples it would have
+/// taken without the break.
+pub struct Checkpoint {
+    /// The time limit isn't stored and is always `None` after loading.
+    pub settings: RenderSettings,
+    /// `SceneData::fingerprint` of the scene that was rendered.
+    pub scene_fingerprint: u64,
+    /// Passes finished when the checkpoint was written.
+    pub passes: u32,
+    pub(crate) film_layers: usize,
+    pub(crate) film: FilmData,
+    pub(crate) pixels: Vec<PixelState>,
+    pub(crate) aovs: Vec<(Aov, Framebuffer)>
+}
+
+impl Checkpoint {
+    /// Reads a checkpoint written by `save`. The sizes in the header are checked against the
+    /// settings and the length of the file before anything is allocated for them, so a
+    /// damaged file gives `CheckpointError::Format`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint, CheckpointError> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(CheckpointError::Format("unknown file type or version".to_string()));
+        }
+
+        let settings = read_settings(&mut reader)?;
+        let scene_fingerprint = read_u64(&mut reader)?;
+        let passes = read_u32(&mut reader)?;
+        let pixel_count = (settings.width as usize).checked_mul(settings.height as usize)
+            .ok_or_else(|| CheckpointError::Format("image too large".to_string()))?;
+
+        // the beauty pass and the filtered aovs, plus one layer per light with light passes
+        let film_layers = read_u32(&mut reader)? as usize;
+        let aov_layers = 1 + settings.aovs.iter().filter(|aov| aov.is_filtered()).count();
+        if film_layers < aov_layers || (!settings.aovs.light_passes() && film_layers != aov_layers) {
+            return Err(CheckpointError::Format("the film layers don't match the aovs".to_string()));
+        }
+
+        let box_count = if settings.filter.filter_type.has_negative_lobes() {pixel_count} else {0};
+        let aov_count = settings.aovs.iter().filter(|aov| !aov.is_filtered()).count();
+        let header_size = reader.stream_position()?;
+        let file_size = reader.get_ref().metadata()?.len();
+        if data_size(pixel_count, film_layers, box_count, aov_count).and_then(|size| size.checked_add(header_size)) != Some(file_size) {
+            return Err(CheckpointError::Format("the file size doesn't match the image size".to_string()));
+        }
+
+        let film_sums = read_vectors(&mut reader, pixel_count * film_layers)?;
+        let film_weights = (0..pixel_count).map(|_| read_f32(&mut reader)).collect::<Result<Vec<f32>, _>>()?;
+        let film = FilmData {
+            sums: film_sums,
+            weights: film_weights,
+            box_sums: read_vectors(&mut reader, box_count * film_layers)?,
+            box_counts: (0..box_count).map(|_| read_u32(&mut reader)).collect::<Result<Vec<u32>, _>>()?
+        };
+
+        let mut pixels = Vec::with_capacity(pixel_count);
+        for _ in 0..pixel_count {
+            pixels.push(PixelState {
+                variance: PixelVariance {
+                    count: read_u32(&mut reader)?,
+                    mean: read_f32(&mut reader)?,
+                    m2: read_f32(&mut reader)?
+                },
+                sample_count: read_u32(&mut reader)?,
+                closest_to_center: read_f32(&mut reader)?
+            });
+        }
+
+        if read_u32(&mut reader)? as usize != aov_count {
+            return Err(CheckpointError::Format("the stored aovs don't match the settings".to_string()));
+        }
+        let mut aovs = Vec::new();
+        for _ in 0..aov_count {
+            let aov = *Aov::ALL.get(read_u8(&mut reader)? as usize).ok_or_else(|| CheckpointError::Format("unknown aov".to_string()))?;
+            if !settings.aovs.contains(aov) || aov.is_filtered() {
+                return Err(CheckpointError::Format(format!("unexpected aov {}", aov.name())));
+            }
+            let mut framebuffer = Framebuffer::new(settings.width, settings.height);
+            for (i, value) in read_vectors(&mut reader, pixel_count)?.into_iter().enumerate() {
+                framebuffer.put_pixel(i as u32 % settings.width, i as u32 / settings.width, value);
+            }
+            aovs.push((aov, framebuffer));
+        }
+
+        Ok(Checkpoint {
+            settings: settings,
+            scene_fingerprint: scene_fingerprint,
+            passes: passes,
+            film_layers: film_layers,
+            film: film,
+            pixels: pixels,
+            aovs: aovs
+        })
+    }
+
+    /// Writes the checkpoint next to `path` first and then moves it in place, so stopping
+    /// the program while writing never destroys the previous checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), CheckpointError> {
+        let path = path.as_ref();
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            write_settings(&mut writer, &self.settings)?;
+            writer.write_all(&self.scene_fingerprint.to_le_bytes())?;
+            write_u32(&mut writer, self.passes)?;
+
+            write_u32(&mut writer, self.film_layers as u32)?;
+            write_vectors(&mut writer, &self.film.sums)?;
+            for weight in self.film.weights.iter() {
+                write_f32(&mut writer, *weight)?;
+            }
+            write_vectors(&mut writer, &self.film.box_sums)?;
+            for count in self.film.box_counts.iter() {
+                write_u32(&mut writer, *count)?;
+            }
+
+            for pixel in self.pixels.iter() {
+                write_u32(&mut writer, pixel.variance.count)?;
+                write_f32(&mut writer, pixel.variance.mean)?;
+                write_f32(&mut writer, pixel.variance.m2)?;
+                write_u32(&mut writer, pixel.sample_count)?;
+                write_f32(&mut writer, pixel.closest_to_center)?;
+            }
+
+            write_u32(&mut writer, self.aovs.len() as u32)?;
+            for (aov, framebuffer) in self.aovs.iter() {
+                writer.write_all(&[*aov as u8])?;
+                write_vectors(&mut writer, framebuffer.pixels())?;
+            }
+            writer.flush()?;
+        }
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Checks that the scene is the one the checkpoint was rendered from, as far as its
+    /// fingerprint tells. The fingerprint covers the scene description and the contents of
+    /// the mesh and buffer files it loads, but not files those refer to in turn, like the
+    /// material libraries of obj files, so editing one of those isn't noticed.
+    pub fn check_scene(&self, scene: &SceneData) -> Result<(), CheckpointError> {
+        if self.scene_fingerprint != scene.fingerprint {
+            return Err(CheckpointError::Mismatch("the scene differs".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checks that the render settings produce the same image as the checkpointed ones.
+    /// Only the camera sample counts, the time limit and the display transform may change.
+    pub fn check_settings(&self, settings: &RenderSettings) -> Result<(), CheckpointError> {
+        let stored = &self.settings;
+        let mismatch = |name: &str| Err(CheckpointError::Mismatch(format!("the {} differs", name)));
+
+        if stored.width != settings.width || stored.height != settings.height {
+            return mismatch("resolution");
+        }
+        if stored.max_ray_depth != settings.max_ray_depth || stored.diffuse_samples != settings.diffuse_samples || stored.specular_samples != settings.specular_samples {
+            return mismatch("ray depth or shading sample count");
+        }
+        if <[f32; 4]>::from(stored.background_color)[..3] != <[f32; 4]>::from(settings.background_color)[..3] {
+            return mismatch("background color");
+        }
+        if stored.seed != settings.seed || stored.sampler != settings.sampler {
+            return mismatch("seed or sampler");
+        }
+        if stored.filter != settings.filter {
+            return mismatch("pixel filter");
+        }
+        if stored.aovs != settings.aovs {
+            return mismatch("set of aovs");
+        }
+        Ok(())
+    }
+}
+
+fn write_settings<W: Write>(writer: &mut W, settings: &RenderSettings) -> io::Result<()> {
+    for value in [settings.width, settings.height, settings.max_ray_depth, settings.diffuse_samples, settings.specular_samples, settings.aa_samples].iter() {
+        write_u32(writer, *value)?;
+    }
+    write_vectors(writer, &[settings.background_color])?;
+    writer.write_all(&settings.seed.to_le_bytes())?;
+    writer.write_all(&[index_of(&SAMPLERS, settings.sampler), index_of(&FILTERS, settings.filter.filter_type)])?;
+    write_f32(writer, settings.filter.radius)?;
+
+    let adaptive = settings.adaptive.unwrap_or_default();
+    writer.write_all(&[settings.adaptive.is_some() as u8])?;
+    write_f32(writer, adaptive.threshold)?;
+    write_u32(writer, adaptive.max_samples)?;
+
+    writer.write_all(&[index_of(&OPERATORS, settings.display.operator)])?;
+    write_f32(writer, settings.display.exposure)?;
+    write_u32(writer, settings.aovs.bits())
+}
+
+fn read_settings<R: Read>(reader: &mut R) -> Result<RenderSettings, CheckpointError> {
+    let mut values = [0u32; 6];
+    for value in values.iter_mut() {
+        *value = read_u32(reader)?;
+    }
+    let background_color = read_vectors(reader, 1)?[0];
+    let mut settings = RenderSettings::new(values[0], values[1], values[2], values[3], values[4], values[5], background_color);
+
+    settings.seed = read_u64(reader)?;
+    settings.sampler = from_index(&SAMPLERS, read_u8(reader)?)?;
+    let filter_type = from_index(&FILTERS, read_u8(reader)?)?;
+    settings.filter = PixelFilter::with_radius(filter_type, read_f32(reader)?);
+
+    let adaptive = read_u8(reader)? != 0;
+    let threshold = read_f32(reader)?;
+    let max_samples = read_u32(reader)?;
+    settings.adaptive = if adaptive {Some(AdaptiveSampling::new(threshold, max_samples))} else {None};
+
+    let operator = from_index(&OPERATORS, read_u8(reader)?)?;
+    settings.display = DisplayTransform::new(operator, read_f32(reader)?);
+    settings.aovs = AovSet::from_bits(read_u32(reader)?);
+
+    if settings.width == 0 || settings.height == 0 {
+        return Err(CheckpointError::Format("empty image".to_string()));
+    }
+    Ok(settings)
+}
+
+// bytes `save` writes after the film layer count, `None` when that doesn't fit a u64
+fn data_size(pixel_count: usize, film_layers: usize, box_count: usize, aov_count: usize) -> Option<u64> {
+    let (pixel_count, film_layers, box_count, aov_count) = (pixel_count as u64, film_layers as u64, box_count as u64, aov_count as u64);
+    // film sums, box sums and aovs
+    let vectors = pixel_count.checked_mul(film_layers)?
+        .checked_add(box_count.checked_mul(film_layers)?)?
+        .checked_add(pixel_count.checked_mul(aov_count)?)?;
+    // film weights, box counts and the five values of every pixel state
+    let values = pixel_count.checked_mul(6)?.checked_add(box_count)?;
+    // the aov count and a byte naming each aov
+    vectors.checked_mul(12)?.checked_add(values.checked_mul(4)?)?.checked_add(4 + aov_count)
+}
+
+fn index_of<T: PartialEq>(values: &[T], value: T) -> u8 {
+    values.iter().position(|v| *v == value).unwrap() as u8
+}
+
+fn from_index<T: Copy>(values: &[T], index: u8) -> Result<T, CheckpointError> {
+    values.get(index as usize).cloned().ok_or_else(|| CheckpointError::Format(format!("unknown setting value {}", index)))
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_vectors<W: Write>(writer: &mut W, vectors: &[Vector]) -> io::Result<()> {
+    for vector in vectors.iter() {
+        write_f32(writer, vector.x())?;
+        write_f32(writer, vector.y())?;
+        write_f32(writer, vector.z())?;
+    }
+    Ok(())
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vectors<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<Vector>> {
+    (0..count).map(|_| Ok(Vector::vec3(read_f32(reader)?, read_f32(reader)?, read_f32(reader)?))).collect()
+}
@@ -0,0 +1,408 @@
+use ray_tracer::math::degree_to_radians;
+use ray_tracer::test_scenes::TEST_SCENES;
+use ray_tracer::RenderSettings;
+use ray_tracer::output::{OutputFormat, ExrPrecision};
+use ray_tracer::tonemap::ToneMapOperator;
+use ray_tracer::aov::AovSet;
+use ray_tracer::sampler::SamplerType;
+use ray_tracer::filter::{FilterType, PixelFilter};
+use ray_tracer::adaptive::AdaptiveSampling;
+use ray_tracer::renderer::SnapshotInterval;
+
+use std::path::{Path, PathBuf};
+use std::{fmt, str::FromStr};
+
+pub const USAGE: &str = "\
+usage: ray_tracer [options] [scene]
+
+The scene is a .scene, .gltf or .glb file or the name of a built in scene,
+run with --list-scenes to see them. Defaults to the 'spheres' scene.
+
+options:
+    -W, --width <pixels>           image width
+    -H, --height <pixels>          image height
+    -d, --depth <n>                maximum ray depth
+        --diffuse-samples <n>      diffuse samples per shading point
+        --specular-samples <n>     specular samples per shading point
+        --aa-samples <n>           anti aliasing samples per pixel axis
+        --adaptive <threshold>     keep sampling pixels whose relative error is above the
+                                   threshold, like 0.01, taking at least aa-samples squared
+        --max-samples <n>          samples per pixel adaptive sampling stops at, defaults to 256
+        --sampler <sampler>        random, stratified, halton, sobol or bluenoise, defaults to sobol
+        --filter <filter>          box, tent, gaussian, mitchell or blackman_harris pixel
+                                   filter, defaults to box
+        --filter-radius <pixels>   filter radius, at least 0.5, defaults to the filter's own
+        --fov <degrees>            vertical field of view of the camera
+    -t, --threads <n>              number of render threads, defaults to the cpu count
+    -o, --output <path>            output image, defaults to image.png
+    -f, --format <format>          png, jpeg, bmp, tiff, exr or hdr, defaults to the output extension
+        --exr-precision <precision> half or float channels for exr output, defaults to half
+        --seed <n>                 seed for the random sampling
+        --tone-map <operator>      clamp, reinhard, aces or filmic for 8 bit output, defaults to clamp
+        --exposure <stops>         exposure adjustment applied before tone mapping
+        --aov <names>              comma separated aovs to render: depth, normal, albedo,
+                                   position, object_id, material_id, direct_diffuse,
+                                   direct_specular, indirect_diffuse, indirect_specular,
+                                   background, sample_count, lights for one pass per
+                                   light, or all.
+                                   Written as exr layers or as separate images like
+                                   image.depth.png
+        --time-limit <seconds>     stop after the time and write the image as far as it got
+        --progressive              render in passes of one sample per pixel and write the
+                                   image so far every 10 seconds
+        --snapshot-passes <n>      progressive, write the image every n passes
+        --snapshot-seconds <s>     progressive, write the image every s seconds
+        --checkpoint <path>        progressive, also save the render state with every
+                                   snapshot and when stopped
+        --resume <path>            continue a progressive render from a checkpoint, using
+                                   its settings, and keep checkpointing to it. Only the
+                                   sample counts, time limit and tone mapping may be changed
+        --bvh-bins <n>             bins the bvh builders sort primitives into, at least 2,
+                                   defaults to 12
+        --bvh-leaf-size <n>        bvh nodes with more primitives are always split,
+                                   defaults to 4
+        --bvh-stats                print how long the bvhs took to build and their quality
+        --list-scenes              list the built in scenes and exit
+    -h, --help                     print this message and exit
+
+Options given on the command line override the settings of a scene file.";
+
+#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    Render(Options),
+    ListScenes,
+    Help
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SceneSource {
+    BuiltIn(String),
+    File(PathBuf)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub scene: SceneSource,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub max_ray_depth: Option<u32>,
+    pub diffuse_samples: Option<u32>,
+    pub specular_samples: Option<u32>,
+    pub aa_samples: Option<u32>,
+    pub fov: Option<f32>,
+    pub threads: Option<usize>,
+    pub output: PathBuf,
+    pub format: OutputFormat,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerType>,
+    pub adaptive_threshold: Option<f32>,
+    pub time_limit: Option<f32>,
+    pub max_samples: Option<u32>,
+    pub filter: Option<FilterType>,
+    pub filter_radius: Option<f32>,
+    pub tone_map: Option<ToneMapOperator>,
+    pub exposure: Option<f32>,
+    pub aovs: Option<AovSet>,
+    pub bvh_bins: Option<usize>,
+    pub bvh_leaf_size: Option<usize>,
+    pub bvh_stats: bool,
+    /// Set for progressive renders.
+    pub progressive: Option<SnapshotInterval>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: Option<PathBuf>
+}
+
+impl Options {
+    /// Overrides the settings loaded with the scene with the ones given on the command line.
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        if let Some(width) = self.width { settings.width = width; }
+        if let Some(height) = self.height { settings.height = height; }
+        if let Some(depth) = self.max_ray_depth { settings.max_ray_depth = depth; }
+        if let Some(samples) = self.diffuse_samples { settings.diffuse_samples = samples; }
+        if let Some(samples) = self.specular_samples { settings.specular_samples = samples; }
+        if let Some(samples) = self.aa_samples { settings.aa_samples = samples; }
+        if let Some(seed) = self.seed { settings.seed = seed; }
+        if let Some(threshold) = self.adaptive_threshold { settings.adaptive.get_or_insert_with(AdaptiveSampling::default).threshold = threshold; }
+        if let Some(samples) = self.max_samples { settings.adaptive.get_or_insert_with(AdaptiveSampling::default).max_samples = samples; }
+        if let Some(sampler) = self.sampler { settings.sampler = sampler; }
+        if let Some(seconds) = self.time_limit { settings.time_limit = Some(seconds); }
+        if let Some(filter_type) = self.filter { settings.filter = PixelFilter::new(filter_type); }
+        if let Some(radius) = self.filter_radius { settings.filter.radius = radius; }
+        if let Some(operator) = self.tone_map { settings.display.operator = operator; }
+        if let Some(exposure) = self.exposure { settings.display.exposure = exposure; }
+        if let Some(aovs) = self.aovs { settings.aovs = aovs; }
+        if let Some(bins) = self.bvh_bins { settings.bvh.bins = bins; }
+        if let Some(size) = self.bvh_leaf_size { settings.bvh.max_leaf_size = size; }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UsageError(String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, UsageError> {
+    let mut args = args.into_iter();
+    let mut scene = None;
+    let mut width = None;
+    let mut height = None;
+    let mut max_ray_depth = None;
+    let mut diffuse_samples = None;
+    let mut specular_samples = None;
+    let mut aa_samples = None;
+    let mut fov = None;
+    let mut threads = None;
+    let mut output = None;
+    let mut format = None;
+    let mut seed = None;
+    let mut sampler = None;
+    let mut adaptive_threshold = None;
+    let mut max_samples = None;
+    let mut filter = None;
+    let mut filter_radius = None;
+    let mut exr_precision = None;
+    let mut tone_map = None;
+    let mut exposure = None;
+    let mut aovs: Option<AovSet> = None;
+    let mut bvh_bins = None;
+    let mut bvh_leaf_size = None;
+    let mut bvh_stats = false;
+    let mut time_limit = None;
+    let mut progressive = false;
+    let mut snapshot_passes = None;
+    let mut snapshot_seconds = None;
+    let mut checkpoint = None;
+    let mut resume = None;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if scene.is_some() {
+                return Err(UsageError(format!("unexpected argument '{}', only one scene can be rendered", arg)));
+            }
+            scene = Some(arg);
+            continue;
+        }
+
+        // accept both `--option value` and `--option=value`
+        let (name, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg.clone(), None)
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--list-scenes" => return Ok(Command::ListScenes),
+            _ => {}
+        }
+
+        let mut value = || match inline_value.clone() {
+            Some(value) => Ok(value),
+            None => match args.next() {
+                Some(value) => Ok(value),
+                None => Err(UsageError(format!("missing value for '{}'", name)))
+            }
+        };
+
+        match name.as_str() {
+            "-W" | "--width" => width = Some(parse_positive(&name, &value()?)?),
+            "-H" | "--height" => height = Some(parse_positive(&name, &value()?)?),
+            "-d" | "--depth" => max_ray_depth = Some(parse_number(&name, &value()?)?),
+            "--diffuse-samples" => diffuse_samples = Some(parse_number(&name, &value()?)?),
+            "--specular-samples" => specular_samples = Some(parse_number(&name, &value()?)?),
+            "--aa-samples" => aa_samples = Some(parse_positive(&name, &value()?)?),
+            "--fov" => {
+                let degrees: f32 = parse_number(&name, &value()?)?;
+                if !(degrees > 0.0 && degrees < 180.0) {
+                    return Err(UsageError(format!("'{}' must be between 0 and 180 degrees, got {}", name, degrees)));
+                }
+                fov = Some(degree_to_radians(degrees));
+            },
+            "-t" | "--threads" => threads = Some(parse_positive(&name, &value()?)? as usize),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "-f" | "--format" => {
+                let value = value()?;
+                match OutputFormat::from_name(&value) {
+                    Some(output_format) => format = Some(output_format),
+                    None => return Err(UsageError(format!("unknown output format '{}', expected png, jpeg, bmp, tiff, exr or hdr", value)))
+                }
+            },
+            "--exr-precision" => {
+                let value = value()?;
+                exr_precision = match value.as_str() {
+                    "half" => Some(ExrPrecision::Half),
+                    "float" => Some(ExrPrecision::Float),
+                    _ => return Err(UsageError(format!("unknown exr precision '{}', expected half or float", value)))
+                };
+            },
+            "--seed" => seed = Some(parse_number(&name, &value()?)?),
+            "--sampler" => {
+                let value = value()?;
+                match SamplerType::from_name(&value) {
+                    Some(sampler_type) => sampler = Some(sampler_type),
+                    None => return Err(UsageError(format!("unknown sampler '{}', expected random, stratified, halton, sobol or bluenoise", value)))
+                }
+            },
+            "--adaptive" => {
+                let threshold: f32 = parse_number(&name, &value()?)?;
+                if !threshold.is_finite() || threshold <= 0.0 {
+                    return Err(UsageError(format!("invalid value '{}' for '{}', the threshold must be positive", threshold, name)));
+                }
+                adaptive_threshold = Some(threshold);
+            },
+            "--max-samples" => max_samples = Some(parse_positive(&name, &value()?)?),
+            "--filter" => {
+                let value = value()?;
+                match FilterType::from_name(&value) {
+                    Some(filter_type) => filter = Some(filter_type),
+                    None => return Err(UsageError(format!("unknown filter '{}', expected box, tent, gaussian, mitchell or blackman_harris", value)))
+                }
+            },
+            "--filter-radius" => {
+                let radius: f32 = parse_number(&name, &value()?)?;
+                if !radius.is_finite() || radius < 0.5 {
+                    return Err(UsageError(format!("invalid value '{}' for '{}', the radius must be at least 0.5", radius, name)));
+                }
+                filter_radius = Some(radius);
+            },
+            "--tone-map" => {
+                let value = value()?;
+                match ToneMapOperator::from_name(&value) {
+                    Some(operator) => tone_map = Some(operator),
+                    None => return Err(UsageError(format!("unknown tone map '{}', expected clamp, reinhard, aces or filmic", value)))
+                }
+            },
+            "--exposure" => {
+                let stops: f32 = parse_number(&name, &value()?)?;
+                if !stops.is_finite() {
+                    return Err(UsageError(format!("invalid value '{}' for '{}'", stops, name)));
+                }
+                exposure = Some(stops);
+            },
+            "--aov" => {
+                let set = aovs.get_or_insert_with(AovSet::new);
+                for aov_name in value()?.split(',').map(str::trim) {
+                    if !set.insert_name(aov_name) {
+                        return Err(UsageError(format!("unknown aov '{}', see --help for the available ones", aov_name)));
+                    }
+                }
+            },
+            "--time-limit" => {
+                let seconds: f32 = parse_number(&name, &value()?)?;
+                if !seconds.is_finite() || seconds <= 0.0 {
+                    return Err(UsageError(format!("invalid value '{}' for '{}'", seconds, name)));
+                }
+                time_limit = Some(seconds);
+            },
+            "--bvh-bins" => {
+                let bins: usize = parse_number(&name, &value()?)?;
+                if bins < 2 {
+                    return Err(UsageError(format!("invalid value '{}' for '{}', a bvh needs at least 2 bins", bins, name)));
+                }
+                bvh_bins = Some(bins);
+            },
+            "--bvh-leaf-size" => bvh_leaf_size = Some(parse_positive(&name, &value()?)? as usize),
+            "--bvh-stats" => bvh_stats = true,
+            "--progressive" => progressive = true,
+            "--checkpoint" => checkpoint = Some(PathBuf::from(value()?)),
+            "--resume" => resume = Some(PathBuf::from(value()?)),
+            "--snapshot-passes" => snapshot_passes = Some(parse_positive(&name, &value()?)?),
+            "--snapshot-seconds" => {
+                let seconds: f32 = parse_number(&name, &value()?)?;
+                if !seconds.is_finite() || seconds <= 0.0 {
+                    return Err(UsageError(format!("invalid value '{}' for '{}'", seconds, name)));
+                }
+                snapshot_seconds = Some(seconds);
+            },
+            _ => return Err(UsageError(format!("unknown option '{}'", name)))
+        }
+    }
+
+    let output = output.unwrap_or_else(|| PathBuf::from("image.png"));
+    let format = match format {
+        Some(format) => format,
+        None => format_from_extension(&output)?
+    };
+
+    let format = match (format, exr_precision) {
+        (OutputFormat::Exr(_), Some(precision)) => OutputFormat::Exr(precision),
+        (_, Some(_)) => return Err(UsageError("'--exr-precision' only applies to exr output".to_string())),
+        (format, None) => format
+    };
+
+    let progressive = match (snapshot_passes, snapshot_seconds) {
+        (None, None) if !progressive && checkpoint.is_none() && resume.is_none() => None,
+        (None, None) => Some(SnapshotInterval { passes: None, seconds: Some(10.0) }),
+        (passes, seconds) => Some(SnapshotInterval { passes: passes, seconds: seconds })
+    };
+
+    let scene = match scene {
+        None => SceneSource::BuiltIn("spheres".to_string()),
+        Some(scene) => {
+            if TEST_SCENES.iter().any(|test_scene| test_scene.0 == scene) {
+                SceneSource::BuiltIn(scene)
+            } else if Path::new(&scene).extension().is_some() {
+                SceneSource::File(PathBuf::from(scene))
+            } else {
+                return Err(UsageError(format!("unknown scene '{}', use --list-scenes to see the built in scenes", scene)));
+            }
+        }
+    };
+
+    Ok(Command::Render(Options {
+        scene: scene,
+        width: width,
+        height: height,
+        max_ray_depth: max_ray_depth,
+        diffuse_samples: diffuse_samples,
+        specular_samples: specular_samples,
+        aa_samples: aa_samples,
+        fov: fov,
+        threads: threads,
+        output: output,
+        format: format,
+        seed: seed,
+        sampler: sampler,
+        adaptive_threshold: adaptive_threshold,
+        time_limit: time_limit,
+        max_samples: max_samples,
+        filter: filter,
+        filter_radius: filter_radius,
+        tone_map: tone_map,
+        exposure: exposure,
+        aovs: aovs,
+        bvh_bins: bvh_bins,
+        bvh_leaf_size: bvh_leaf_size,
+        bvh_stats: bvh_stats,
+        progressive: progressive,
+        checkpoint: checkpoint,
+        resume: resume
+    }))
+}
+
+fn format_from_extension(path: &Path) -> Result<OutputFormat, UsageError> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    match OutputFormat::from_name(extension) {
+        Some(format) => Ok(format),
+        None => Err(UsageError(format!("can't tell the image format of '{}', use --format", path.display())))
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, UsageError> {
+    match value.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => Err(UsageError(format!("invalid value '{}' for '{}'", value, name)))
+    }
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<u32, UsageError> {
+    let number: u32 = parse_number(name, value)?;
+    if number == 0 {
+        return Err(UsageError(format!("'{}' must be greater than 0", name)));
+    }
+    Ok(number)
+}
@@ -0,0 +1,225 @@
+use crate::filter::PixelFilter;
+use crate::framebuffer::Framebuffer;
+use crate::vector_simd::Vector;
+
+use std::sync::{Mutex, MutexGuard};
+
+struct FilmRow {
+    // `layers` values per pixel, one after the other
+    sums: Vec<Vector>,
+    weights: Vec<f32>,
+    // plain sums and counts of the samples taken inside each pixel, only kept when the
+    // filter has negative lobes, for pixels whose weights sum to zero or less
+    box_sums: Vec<Vector>,
+    box_counts: Vec<u32>
+}
+
+// the samples of the current pass, at most one per pixel
+struct PassRow {
+    taken: Vec<bool>,
+    positions: Vec<(f32, f32)>,
+    values: Vec<Vector>
+}
+
+/// The weighted sums of a film, row by row, for checkpoints.
+pub(crate) struct FilmData {
+    pub(crate) sums: Vec<Vector>,
+    pub(crate) weights: Vec<f32>,
+    /// Empty unless the filter has negative lobes.
+    pub(crate) box_sums: Vec<Vector>,
+    pub(crate) box_counts: Vec<u32>
+}
+
+/// Accumulates samples weighted by a pixel filter. A sample counts for every pixel whose
+/// center lies within the filter radius, so neighbouring pixels share samples. Each sample
+/// carries the same number of layers, like the beauty pass and its aovs, which are all
+/// filtered the same way.
+///
+/// Samples are taken in passes of at most one sample per pixel. They are kept until the
+/// pass ends and then every pixel gathers the samples around it in a fixed order, so the
+/// sums don't depend on which thread took a sample or when.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: PixelFilter,
+    layers: usize,
+    rows: Vec<FilmRow>,
+    pass: Vec<Mutex<PassRow>>
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: PixelFilter, layers: usize) -> Self {
+        let box_size = if filter.filter_type.has_negative_lobes() {width as usize} else {0};
+        let rows = (0..height).map(|_| FilmRow {
+            sums: vec![Vector::vec3(0.0, 0.0, 0.0); width as usize * layers],
+            weights: vec![0.0; width as usize],
+            box_sums: vec![Vector::vec3(0.0, 0.0, 0.0); box_size * layers],
+            box_counts: vec![0; box_size]
+        }).collect();
+        let pass = (0..height).map(|_| Mutex::new(PassRow {
+            taken: vec![false; width as usize],
+            positions: vec![(0.0, 0.0); width as usize],
+            values: vec![Vector::vec3(0.0, 0.0, 0.0); width as usize * layers]
+        })).collect();
+
+        Self {
+            width: width,
+            height: height,
+            filter: filter,
+            layers: layers,
+            rows: rows,
+            pass: pass
+        }
+    }
+
+    /// Number of values every sample carries.
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    /// Adds the sample pixel (x, y) took in the current pass, at a position in raster space
+    /// where the pixel covers x to x + 1 and y to y + 1. It is filtered into the pixels
+    /// around it by `end_pass`.
+    pub fn add_sample(&self, x: u32, y: u32, position: (f32, f32), values: &[Vector]) {
+        let mut row = self.pass[y as usize].lock().unwrap();
+        assert!(!row.taken[x as usize], "a pixel can only take one sample per pass");
+
+        let offset = x as usize * self.layers;
+        row.taken[x as usize] = true;
+        row.positions[x as usize] = position;
+        row.values[offset..offset + self.layers].copy_from_slice(values);
+    }
+
+    /// Filters the samples of the current pass into the film, splitting the rows between
+    /// `threads` threads. Every pixel adds the samples around it row by row, so the result is
+    /// the same for any number of threads.
+    pub fn end_pass(&mut self, threads: usize) {
+        let rows_per_thread = self.rows.len().div_ceil(threads.max(1)).max(1);
+        let mut rows = std::mem::take(&mut self.rows);
+        {
+            let film = &*self;
+            let pass: Vec<_> = self.pass.iter().map(|row| row.lock().unwrap()).collect();
+            let pass = &pass;
+
+            crossbeam_utils::thread::scope(|s| {
+                for (chunk, rows) in rows.chunks_mut(rows_per_thread).enumerate() {
+                    s.spawn(move |_| {
+                        for (i, row) in rows.iter_mut().enumerate() {
+                            film.gather(row, (chunk * rows_per_thread + i) as u32, pass);
+                        }
+                    });
+                }
+            }).unwrap();
+        }
+        self.rows = rows;
+
+        for row in self.pass.iter_mut() {
+            let row = row.get_mut().unwrap();
+            for taken in row.taken.iter_mut() {
+                *taken = false;
+            }
+        }
+    }
+
+    // adds the samples of the pass that reach the pixels of row `y`, sources in raster order
+    fn gather(&self, row: &mut FilmRow, y: u32, pass: &[MutexGuard<PassRow>]) {
+        let radius = self.filter.radius;
+        let reach = radius.ceil() as i64 + 1;
+        let source_range = |pixel: u32, size: u32| {
+            ((pixel as i64 - reach).max(0) as u32)..((pixel as i64 + reach + 1).min(size as i64) as u32)
+        };
+
+        for x in 0..self.width {
+            let offset = x as usize * self.layers;
+
+            for source_y in source_range(y, self.height) {
+                let source = &pass[source_y as usize];
+
+                for source_x in source_range(x, self.width) {
+                    if !source.taken[source_x as usize] {
+                        continue;
+                    }
+
+                    // a sample counts for the pixels `pixel_range` gives for its position
+                    let (sample_x, sample_y) = source.positions[source_x as usize];
+                    let (x_min, x_max) = pixel_range(sample_x, radius, self.width);
+                    let (y_min, y_max) = pixel_range(sample_y, radius, self.height);
+                    if x < x_min || x >= x_max || y < y_min || y >= y_max {
+                        continue;
+                    }
+
+                    let weight = self.filter.evaluate(sample_x - (x as f32 + 0.5), sample_y - (y as f32 + 0.5));
+                    let values = &source.values[source_x as usize * self.layers..(source_x as usize + 1) * self.layers];
+                    for (layer, value) in values.iter().enumerate() {
+                        row.sums[offset + layer] += *value * weight;
+                    }
+                    row.weights[x as usize] += weight;
+
+                    if !row.box_counts.is_empty() && source_x == x && source_y == y {
+                        for (layer, value) in values.iter().enumerate() {
+                            row.box_sums[offset + layer] += *value;
+                        }
+                        row.box_counts[x as usize] += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn data(&self) -> FilmData {
+        let mut data = FilmData {
+            sums: Vec::with_capacity(self.rows.len() * self.width as usize * self.layers),
+            weights: Vec::with_capacity(self.rows.len() * self.width as usize),
+            box_sums: Vec::new(),
+            box_counts: Vec::new()
+        };
+        for row in self.rows.iter() {
+            data.sums.extend_from_slice(&row.sums);
+            data.weights.extend_from_slice(&row.weights);
+            data.box_sums.extend_from_slice(&row.box_sums);
+            data.box_counts.extend_from_slice(&row.box_counts);
+        }
+        data
+    }
+
+    // the data has to come from a film with the same size, filter and layers
+    pub(crate) fn set_data(&mut self, data: &FilmData) {
+        let row_sums = self.width as usize * self.layers;
+        let row_weights = self.width as usize;
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            row.sums.copy_from_slice(&data.sums[y * row_sums..(y + 1) * row_sums]);
+            row.weights.copy_from_slice(&data.weights[y * row_weights..(y + 1) * row_weights]);
+            if !row.box_counts.is_empty() {
+                row.box_sums.copy_from_slice(&data.box_sums[y * row_sums..(y + 1) * row_sums]);
+                row.box_counts.copy_from_slice(&data.box_counts[y * row_weights..(y + 1) * row_weights]);
+            }
+        }
+    }
+
+    /// Divides the weighted sums of a layer by the filter weights. Pixels whose weights sum
+    /// to zero or less, which negative lobes can cause next to the image border, fall back
+    /// to the plain average of their own samples.
+    pub fn resolve(&self, layer: usize) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+
+        for (y, row) in self.rows.iter().enumerate() {
+            for x in 0..self.width as usize {
+                let weight = row.weights[x];
+                if weight > 0.0 {
+                    framebuffer.put_pixel(x as u32, y as u32, row.sums[x * self.layers + layer] / weight);
+                } else if row.box_counts.get(x).is_some_and(|count| *count > 0) {
+                    framebuffer.put_pixel(x as u32, y as u32, row.box_sums[x * self.layers + layer] / row.box_counts[x] as f32);
+                }
+            }
+        }
+
+        framebuffer
+    }
+}
+
+// pixels whose centers are within [-radius, radius) of the sample, clipped to the image
+fn pixel_range(position: f32, radius: f32, size: u32) -> (u32, u32) {
+    let min = (position - 0.5 - radius).floor() + 1.0;
+    let max = (position - 0.5 + radius).floor() + 1.0;
+    (min.max(0.0) as u32, (max.max(0.0) as u32).min(size))
+}
@@ -0,0 +1,117 @@
+use std::f32::consts;
+
+/// Reconstruction filters that weight the samples around a pixel center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterType {
+    /// Every sample within the radius counts the same, a radius of half a pixel only uses
+    /// the samples inside the pixel.
+    Box,
+    /// Weights fall off linearly to the radius.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted to reach zero
+    /// at the radius.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, sharper than the gaussian with slightly
+    /// negative lobes.
+    Mitchell,
+    /// Four term Blackman-Harris window, close to a gaussian but with less blur.
+    BlackmanHarris
+}
+
+impl FilterType {
+    pub fn from_name(name: &str) -> Option<FilterType> {
+        match name.to_ascii_lowercase().as_str() {
+            "box" => Some(FilterType::Box),
+            "tent" | "triangle" => Some(FilterType::Tent),
+            "gaussian" => Some(FilterType::Gaussian),
+            "mitchell" => Some(FilterType::Mitchell),
+            "blackman_harris" | "blackman-harris" => Some(FilterType::BlackmanHarris),
+            _ => None
+        }
+    }
+
+    /// Whether the filter has negative weights, so the weights of a pixel can sum to zero
+    /// or less.
+    pub fn has_negative_lobes(self) -> bool {
+        self == FilterType::Mitchell
+    }
+
+    /// Radius in pixels used when none is given.
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.0,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.0,
+            FilterType::BlackmanHarris => 1.5
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelFilter {
+    pub filter_type: FilterType,
+    /// Radius in pixels, at least half a pixel so every pixel gets samples.
+    pub radius: f32
+}
+
+impl PixelFilter {
+    pub fn new(filter_type: FilterType) -> Self {
+        Self::with_radius(filter_type, filter_type.default_radius())
+    }
+
+    pub fn with_radius(filter_type: FilterType, radius: f32) -> Self {
+        Self {
+            filter_type: filter_type,
+            radius: radius
+        }
+    }
+
+    /// Weight of a sample at the offset from a pixel center, in pixels.
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        match self.filter_type {
+            FilterType::Box => 1.0,
+            _ => self.evaluate_1d(dx) * self.evaluate_1d(dy)
+        }
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        let x = x.abs();
+
+        match self.filter_type {
+            FilterType::Box => 1.0,
+            FilterType::Tent => (r - x).max(0.0),
+            FilterType::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |v: f32| (-(v * v) / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(r)).max(0.0)
+            },
+            FilterType::Mitchell => mitchell_1d(2.0 * x / r),
+            FilterType::BlackmanHarris => {
+                let t = 2.0 * consts::PI * (x + r) / (2.0 * r);
+                0.358_75 - 0.488_29 * t.cos() + 0.141_28 * (2.0 * t).cos() - 0.011_68 * (3.0 * t).cos()
+            }
+        }
+    }
+}
+
+impl Default for PixelFilter {
+    fn default() -> Self {
+        Self::new(FilterType::Box)
+    }
+}
+
+// cubic on [0, 2] with b = c = 1/3
+fn mitchell_1d(x: f32) -> f32 {
+    const B: f32 = 1.0 / 3.0;
+    const C: f32 = 1.0 / 3.0;
+
+    if x > 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)) / 6.0
+    } else {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)) / 6.0
+    }
+}
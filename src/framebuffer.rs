@@ -0,0 +1,85 @@
+use crate::vector_simd::Vector;
+use crate::output::{self, OutputFormat, OutputError};
+use crate::tonemap::DisplayTransform;
+use crate::aov::{Aov, AovSet};
+
+use std::path::{Path, PathBuf};
+
+/// Rendered image holding the linear radiance of each pixel, stored row by row from the
+/// top left corner.
+#[derive(Clone)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Vector>
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width,
+            height: height,
+            pixels: vec![Vector::vec3(0.0, 0.0, 0.0); (width * height) as usize]
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Vector {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: Vector) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn pixels(&self) -> &[Vector] {
+        &self.pixels
+    }
+
+    /// Converts to an 8 bit sRGB image using the display transform.
+    pub fn to_rgb_image(&self, display: &DisplayTransform) -> image::RgbImage {
+        let mut image = image::RgbImage::new(self.width, self.height);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let output = display.apply(self.get_pixel(x, y)) * 255.0 + 0.5;
+            let (r, g, b, _) = output.into();
+            *pixel = image::Rgb([r as u8, g as u8, b as u8]);
+        }
+        image
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: OutputFormat, display: &DisplayTransform) -> Result<(), OutputError> {
+        output::save(self, path, format, display)
+    }
+}
+
+/// The beauty pass together with the aovs and per light passes rendered alongside it.
+#[derive(Clone)]
+pub struct RenderBuffers {
+    pub beauty: Framebuffer,
+    pub aovs: Vec<(Aov, Framebuffer)>,
+    /// Direct light of every light in the order of the scene lights, empty unless the per
+    /// light passes were asked for.
+    pub lights: Vec<Framebuffer>
+}
+
+impl RenderBuffers {
+    pub fn new(width: u32, height: u32, aovs: AovSet, light_count: usize) -> Self {
+        let light_count = if aovs.light_passes() {light_count} else {0};
+
+        Self {
+            beauty: Framebuffer::new(width, height),
+            aovs: aovs.iter().map(|aov| (aov, Framebuffer::new(width, height))).collect(),
+            lights: (0..light_count).map(|_| Framebuffer::new(width, height)).collect()
+        }
+    }
+
+    pub fn aov(&self, aov: Aov) -> Option<&Framebuffer> {
+        self.aovs.iter().find(|(buffer_aov, _)| *buffer_aov == aov).map(|(_, framebuffer)| framebuffer)
+    }
+
+    /// Saves the beauty pass to `path`. Exr files get the aovs and light passes as extra
+    /// layers, the other formats write each of them to its own file next to it, like
+    /// `image.depth.png` or `image.light_0.png`. Returns the paths of all written files.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: OutputFormat, display: &DisplayTransform) -> Result<Vec<PathBuf>, OutputError> {
+        output::save_buffers(self, path.as_ref(), format, display)
+    }
+}
@@ -1,5 +1,3 @@
-#![allow(dead_code)]
-
 use crate::vector_simd::{Vector};
 use std::{f32, fmt};
 use std::f32::consts;
@@ -17,12 +15,17 @@ impl Rectangle {
 
 pub struct Vertex {
     pub pos: Vector,
-    pub normal: Vector
+    pub normal: Vector,
+    pub texture_coord: Vector
 }
 
 impl Vertex {
     pub fn new(pos: Vector, norm: Vector) -> Self {
-        Self { pos: pos, normal: norm }
+        Self { pos: pos, normal: norm, texture_coord: Vector::vec2(0.0, 0.0) }
+    }
+
+    pub fn with_texture_coord(pos: Vector, norm: Vector, texture_coord: Vector) -> Self {
+        Self { pos: pos, normal: norm, texture_coord: texture_coord }
     }
 }
 
@@ -30,6 +33,8 @@ pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
     pub num_tris: u32,
+    // per vertex colors, empty when the mesh has none
+    pub colors: Vec<Vector>,
 }
 
 pub fn create_triangle() -> Mesh {
@@ -47,6 +52,7 @@ pub fn create_triangle() -> Mesh {
         vertices: vertices,
         indices: indices,
         num_tris: 1,
+        colors: Vec::new(),
     }
 }
 
@@ -93,6 +99,7 @@ pub fn create_plane(width: f32, depth: f32, sub_div_width: u32, sub_div_depth: u
         vertices: vertices,
         indices: indices,
         num_tris: tri_count,
+        colors: Vec::new(),
     }
 }
 
@@ -169,6 +176,7 @@ pub fn create_box(width: f32, height: f32, depth: f32) -> Mesh {
         vertices: vertices,
         indices: indices,
         num_tris: 12,
+        colors: Vec::new(),
     }
 }
 
@@ -242,7 +250,104 @@ pub fn create_sphere(radius: f32, slices: u32, stacks: u32) -> Mesh {
         vertices: vertices,
         indices: indices,
         num_tris: tris,
+        colors: Vec::new(),
+    }
+}
+
+/// Smooth per vertex normals from the area weighted normals of the faces sharing each vertex.
+pub fn generate_normals(positions: &[Vector], indices: &[u32]) -> Vec<Vector> {
+    let mut normals = vec![Vector::vec3(0.0, 0.0, 0.0); positions.len()];
+
+    for triangle in indices.chunks(3) {
+        let a = positions[triangle[0] as usize];
+        let b = positions[triangle[1] as usize];
+        let c = positions[triangle[2] as usize];
+        // not normalized so larger faces contribute more to the smoothed normal
+        let face_normal = (b - a).vec3_cross(c - a);
+
+        for &index in triangle {
+            normals[index as usize] += face_normal;
+        }
+    }
+
+    for normal in normals.iter_mut() {
+        *normal = if normal.vec3_dot_f32(*normal) > 0.0 {normal.vec3_normalize()} else {Vector::vec3(0.0, 1.0, 0.0)};
+    }
+
+    normals
+}
+
+/// Ear clipping triangulation of a planar polygon, returns triangles as indices into the polygon.
+pub fn triangulate(polygon: &[Vector]) -> Vec<[usize; 3]> {
+    let count = polygon.len();
+
+    if count == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, robust for slightly non planar polygons
+    let mut normal = Vector::vec3(0.0, 0.0, 0.0);
+    for i in 0..count {
+        normal += polygon[i].vec3_cross(polygon[(i + 1) % count]);
+    }
+
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut triangles = Vec::new();
+
+    if normal.vec3_dot_f32(normal) <= 0.0 {
+        for i in 1..(count - 1) {
+            triangles.push([0, i, i + 1]);
+        }
+        return triangles;
     }
+
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let mut clipped = false;
+
+        for i in 0..len {
+            let prev = remaining[(i + len - 1) % len];
+            let current = remaining[i];
+            let next = remaining[(i + 1) % len];
+
+            let a = polygon[prev];
+            let b = polygon[current];
+            let c = polygon[next];
+
+            // reflex corners can't be ears
+            if (b - a).vec3_cross(c - b).vec3_dot_f32(normal) <= 0.0 {
+                continue;
+            }
+
+            let contains_other = remaining.iter()
+                .filter(|&&j| j != prev && j != current && j != next)
+                .any(|&j| point_in_triangle(polygon[j], a, b, c, normal));
+
+            if !contains_other {
+                triangles.push([prev, current, next]);
+                remaining.remove(i);
+                clipped = true;
+                break;
+            }
+        }
+
+        // degenerate input, fall back to a fan over what is left
+        if !clipped {
+            for i in 1..(remaining.len() - 1) {
+                triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+            }
+            return triangles;
+        }
+    }
+
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+fn point_in_triangle(p: Vector, a: Vector, b: Vector, c: Vector, normal: Vector) -> bool {
+    (b - a).vec3_cross(p - a).vec3_dot_f32(normal) >= 0.0 &&
+        (c - b).vec3_cross(p - b).vec3_dot_f32(normal) >= 0.0 &&
+        (a - c).vec3_cross(p - c).vec3_dot_f32(normal) >= 0.0
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -250,6 +355,12 @@ pub struct BoundingBox {
     pub bounds:[Vector; 2]
 }
 
+impl Default for BoundingBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BoundingBox {
     pub fn new() -> Self {
         Self {
@@ -295,11 +406,11 @@ impl BoundingBox {
         let d = self.diagonal();
 
         if d.x() > d.y() && d.x() > d.z() {
-            return 0;
+            0
         } else if d.y() > d.z() {
-            return 1;
+            1
         } else {
-            return 2;
+            2
         }
     }
 
@@ -477,12 +588,9 @@ impl BoundingSpehere {
             return false;            
         }
 
-        let mut t0;
-        let t1;
-
         let thc = (self.radius_sqrd - d2).sqrt(); 
-        t0 = tca - thc; 
-        t1 = tca + thc; 
+        let mut t0 = tca - thc; 
+        let t1 = tca + thc; 
 
         if t0 < 0.0 { 
             t0 = t1;
@@ -495,6 +603,8 @@ impl BoundingSpehere {
     }
 }
 
+/// Intersects a ray with the parallelogram at `pos` spanned by `v1` and `v2`, the point
+/// where the ray hits it is written to `hit_at`.
 pub fn intersect_plane(ray_origin: Vector, ray_dir: Vector, pos: Vector, normal: Vector, v1: Vector, v2: Vector, hit_at: &mut Vector) -> bool {
     
     let denom = ray_dir.vec3_dot_f32(normal);
@@ -504,16 +614,15 @@ pub fn intersect_plane(ray_origin: Vector, ray_dir: Vector, pos: Vector, normal:
         let t = dist.vec3_dot_f32(normal) / denom;
 
         if t > 0.0 { 
-            let hit_at = ray_origin + ray_dir * t;
-            let diff = hit_at - pos; 
+            let hit = ray_origin + ray_dir * t;
+            let diff = hit - pos; 
 
             let q1 = v1.vec3_dot_f32(diff);
             let q2 = v2.vec3_dot_f32(diff);
 
             if 0.0 <= q1 && q1 <= v1.vec3_dot_f32(v1) && 0.0 <= q2 && q2 <= v2.vec3_dot_f32(v2) {
+                *hit_at = hit;
                 return true;
-            } else {
-                return false;
             }
         }
     }
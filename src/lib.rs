@@ -0,0 +1,188 @@
+//! A multi-threaded CPU ray tracer.
+//!
+//! A scene is described by [`SceneData`], built in code with [`SceneBuilder`], loaded from a
+//! scene file with [`scene_file::load_scene_file`] or imported with the [`loaders`]. A
+//! [`Renderer`] renders it with a set of [`RenderSettings`] into [`RenderBuffers`] holding a
+//! [`Framebuffer`] of linear radiance and the selected [`aov`]s, which can be saved as 8 bit
+//! images or as exr or hdr with [`output`].
+//!
+//! ```no_run
+//! use ray_tracer::{Camera, Lights, Material, OutputFormat, RenderSettings, Renderer, SceneBuilder, Vector};
+//! use ray_tracer::geometry::create_sphere;
+//! use ray_tracer::shading::lights::DirectionalLight;
+//!
+//! let scene = SceneBuilder::new()
+//!     .camera(Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0)))
+//!     .add_object(create_sphere(0.5, 40, 20), Material::default(), Vector::vec3(0.0, 0.0, -2.0), Vector::vec3(1.0, 1.0, 1.0), Vector::vec3(0.0, 0.0, 0.0))
+//!     .add_light(Lights::Directional(DirectionalLight::new(Vector::vec3(0.0, -1.0, -1.0), 1.5, Vector::vec3(1.0, 1.0, 1.0))))
+//!     .build()
+//!     .unwrap();
+//!
+//! let (buffers, stats) = Renderer::new(RenderSettings::default()).render(&scene);
+//! buffers.save("image.png", OutputFormat::Png, &RenderSettings::default().display).unwrap();
+//! println!("{}", stats);
+//! ```
+
+#![allow(clippy::redundant_field_names)]
+
+pub mod vector_simd;
+pub mod matrix;
+pub mod geometry;
+pub mod shading;
+pub mod ray_tracer;
+pub mod scene;
+pub mod math;
+pub mod test_scenes;
+pub mod bvh;
+pub mod camera;
+pub mod scene_file;
+pub mod loaders;
+pub mod framebuffer;
+pub mod renderer;
+pub mod output;
+pub mod tonemap;
+pub mod aov;
+pub mod sampler;
+pub mod filter;
+pub mod film;
+pub mod adaptive;
+pub mod checkpoint;
+
+pub use vector_simd::Vector;
+pub use scene::{SceneData, SceneBuilder, SceneError};
+pub use camera::Camera;
+pub use shading::{materials::Material, lights::Lights};
+pub use geometry::Mesh;
+pub use framebuffer::{Framebuffer, RenderBuffers};
+pub use renderer::{Renderer, CancellationToken};
+pub use output::OutputFormat;
+
+use tonemap::DisplayTransform;
+use aov::AovSet;
+use sampler::SamplerType;
+use filter::PixelFilter;
+use adaptive::AdaptiveSampling;
+use bvh::BvhSettings;
+
+use std::{fmt, ops};
+
+/// Quality settings for a render. Sample counts are per shading point except `aa_samples`,
+/// its square is the number of camera samples per pixel.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub width:u32,
+    pub height:u32,
+    pub max_ray_depth: u32,
+    pub diffuse_samples: u32,
+    pub specular_samples: u32,
+    pub aa_samples: u32,
+    pub background_color: Vector,
+    /// Seed of all random sampling, a seed renders the same image on any number of threads.
+    pub seed: u64,
+    pub sampler: SamplerType,
+    /// Reconstruction filter the camera samples are splatted with.
+    pub filter: PixelFilter,
+    /// Takes more samples in noisy pixels when set, `aa_samples` squared is the minimum.
+    pub adaptive: Option<AdaptiveSampling>,
+    /// Seconds after which the render stops and returns the image as far as it got, limits
+    /// too long to represent leave the render unlimited.
+    pub time_limit: Option<f32>,
+    /// Applied when the radiance is written to an 8 bit image.
+    pub display: DisplayTransform,
+    /// Auxiliary buffers rendered next to the beauty pass.
+    pub aovs: AovSet,
+    /// How the loaders build the bvhs of a scene, doesn't change the image.
+    pub bvh: BvhSettings
+}
+
+impl RenderSettings {
+    pub fn new(width: u32, height: u32, ray_depth: u32, diffuse_samples: u32, specular_samples: u32, aa_samples: u32, background_color: Vector) -> Self {
+        Self {width: width, height: height, max_ray_depth: ray_depth, diffuse_samples: diffuse_samples, specular_samples: specular_samples, aa_samples: aa_samples, background_color: background_color, seed: 0, sampler: SamplerType::Sobol, filter: PixelFilter::default(), adaptive: None, time_limit: None, display: DisplayTransform::default(), aovs: AovSet::new(), bvh: BvhSettings::default()}
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings::new(1280, 720, 2, 4, 4, 7, Vector::vec3(0.86, 0.92, 1.0))
+    }
+}
+
+/// Counters collected while rendering, summed over all render threads.
+#[derive(Clone, Copy, Debug)]
+pub struct Stats {
+    pub num_rays_shot: u128,
+    pub num_camera_samples: u128,
+    pub num_tringle_tests: u128,
+    pub num_triangles_intersected: u128,
+    pub num_shadow_rays: u128,
+    /// Shadow rays that hit something, their search stopped at that first hit.
+    pub num_occluded_shadow_rays: u128,
+    /// Triangle tests done by shadow rays, also part of `num_tringle_tests`.
+    pub num_shadow_triangle_tests: u128,
+    /// Triangle tests done by camera rays, also part of `num_tringle_tests`.
+    pub num_camera_triangle_tests: u128,
+    /// Shadow rays that were also traced for the closest hit, one in every 64, with the
+    /// triangle tests they took each way. The closest hit tests aren't part of
+    /// `num_tringle_tests`.
+    pub num_compared_shadow_rays: u128,
+    pub num_compared_any_hit_tests: u128,
+    pub num_compared_closest_hit_tests: u128,
+    pub render_time: f64,
+    /// Set when the render was cancelled or ran out of time.
+    pub stopped_early: bool
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats {
+            num_rays_shot: 0,
+            num_camera_samples: 0,
+            num_tringle_tests: 0,
+            num_triangles_intersected: 0,
+            num_shadow_rays: 0,
+            num_occluded_shadow_rays: 0,
+            num_shadow_triangle_tests: 0,
+            num_camera_triangle_tests: 0,
+            num_compared_shadow_rays: 0,
+            num_compared_any_hit_tests: 0,
+            num_compared_closest_hit_tests: 0,
+            render_time: 0.0,
+            stopped_early: false
+        }
+    }
+}
+
+impl ops::AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.num_rays_shot += other.num_rays_shot;
+        self.num_camera_samples += other.num_camera_samples;
+        self.num_tringle_tests += other.num_tringle_tests;
+        self.num_triangles_intersected += other.num_triangles_intersected;
+        self.num_shadow_rays += other.num_shadow_rays;
+        self.num_occluded_shadow_rays += other.num_occluded_shadow_rays;
+        self.num_shadow_triangle_tests += other.num_shadow_triangle_tests;
+        self.num_camera_triangle_tests += other.num_camera_triangle_tests;
+        self.num_compared_shadow_rays += other.num_compared_shadow_rays;
+        self.num_compared_any_hit_tests += other.num_compared_any_hit_tests;
+        self.num_compared_closest_hit_tests += other.num_compared_closest_hit_tests;
+        self.render_time += other.render_time;
+        self.stopped_early |= other.stopped_early;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let per_ray = |tests: u128, rays: u128| if rays > 0 {tests as f64 / rays as f64} else {0.0};
+        write!(f,
+            "number of rays shot: {},\n number of camera samples: {},\n number of triangles tested: {},\n number of triangles intersected: {},\n number of shadow rays: {} ({} occluded, stopped at their first hit),\n number of triangles tested by shadow rays: {},\n triangle tests per camera ray: {:.2}, per shadow ray: {:.2},\n",
+            self.num_rays_shot, self.num_camera_samples, self.num_tringle_tests, self.num_triangles_intersected, self.num_shadow_rays, self.num_occluded_shadow_rays, self.num_shadow_triangle_tests,
+            per_ray(self.num_camera_triangle_tests, self.num_camera_samples), per_ray(self.num_shadow_triangle_tests, self.num_shadow_rays)
+        )?;
+        if self.num_compared_closest_hit_tests > 0 {
+            let saved = self.num_compared_closest_hit_tests.saturating_sub(self.num_compared_any_hit_tests);
+            writeln!(f, " stopping shadow rays at the first hit saved {} of {} triangle tests ({:.1}%) on {} sampled shadow rays,",
+                saved, self.num_compared_closest_hit_tests, saved as f64 * 100.0 / self.num_compared_closest_hit_tests as f64, self.num_compared_shadow_rays)?;
+        }
+        write!(f, " image generated in: {}", self.render_time)
+    }
+}
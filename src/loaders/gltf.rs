@@ -0,0 +1,355 @@
+use crate::geometry::{Mesh, Vertex, BoundingBox, generate_normals};
+use crate::scene::*;
+use crate::shading::{materials::Material, lights::*};
+use crate::camera::Camera;
+use crate::bvh::{BvhSettings, build_bvh_with_settings};
+use crate::matrix::Matrix;
+use crate::math::{clamp, degree_to_radians};
+use crate::vector_simd::Vector;
+
+use ::gltf::buffer::Source;
+use ::gltf::camera::Projection;
+use ::gltf::khr_lights_punctual::Kind;
+use ::gltf::mesh::Mode;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::{f32, f32::consts, fmt, fs, io};
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    Gltf(::gltf::Error),
+    Buffer(String),
+    NoGeometry
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Io(error) => write!(f, "{}", error),
+            GltfError::Gltf(error) => write!(f, "{}", error),
+            GltfError::Buffer(message) => write!(f, "{}", message),
+            GltfError::NoGeometry => write!(f, "the scene contains no triangle meshes")
+        }
+    }
+}
+
+impl From<io::Error> for GltfError {
+    fn from(error: io::Error) -> Self {
+        GltfError::Io(error)
+    }
+}
+
+impl From<::gltf::Error> for GltfError {
+    fn from(error: ::gltf::Error) -> Self {
+        GltfError::Gltf(error)
+    }
+}
+
+/// Loads the default scene of a .gltf or .glb file. Every node that uses a mesh becomes an
+/// instance of it with the node transform, KHR_lights_punctual lights become scene lights and the first camera found
+/// becomes the scene camera. Without a camera the view is framed around the geometry.
+/// Primitives and lights that can't be imported as they are end up in `SceneData::warnings`.
+pub fn load_gltf<P: AsRef<Path>>(path: P) -> Result<SceneData, GltfError> {
+    load_gltf_with_settings(path, &BvhSettings::default())
+}
+
+/// Loads a file like `load_gltf`, building the bvhs with the given settings.
+pub fn load_gltf_with_settings<P: AsRef<Path>>(path: P, bvh_settings: &BvhSettings) -> Result<SceneData, GltfError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path)?;
+    let ::gltf::Gltf { document, blob } = ::gltf::Gltf::from_slice(&bytes)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.add(&bytes);
+    let buffers = load_buffers(&document, blob, base_dir, &mut fingerprint)?;
+
+    let mut state = ImportState {
+        buffers: buffers,
+        meshes: HashMap::new(),
+        scene_objects: Vec::new(),
+        lights: Vec::new(),
+        camera: None,
+        warnings: Vec::new()
+    };
+
+    let scene = match document.default_scene() {
+        Some(scene) => Some(scene),
+        None => document.scenes().next()
+    };
+
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            visit_node(&node, Matrix::identity(), &mut state);
+        }
+    }
+
+    if state.scene_objects.is_empty() {
+        return Err(GltfError::NoGeometry);
+    }
+
+    let camera = match state.camera {
+        Some(camera) => camera,
+        None => frame_objects(&state.scene_objects)
+    };
+
+    let (bvh, indices, bvh_stats) = build_bvh_with_settings(&state.scene_objects, bvh_settings);
+
+    Ok(SceneData {
+        bvh: bvh,
+        object_indices: indices,
+        scene_objects: state.scene_objects,
+        lights: state.lights,
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: fingerprint.finish(),
+        warnings: state.warnings
+    })
+}
+
+struct ImportState {
+    buffers: Vec<Vec<u8>>,
+    // primitives by mesh and primitive index, `None` for the ones that were skipped
+    meshes: HashMap<(usize, usize), Option<Arc<MeshResource>>>,
+    scene_objects: Vec<SceneObject>,
+    lights: Vec<Lights>,
+    camera: Option<Camera>,
+    warnings: Vec<String>
+}
+
+fn visit_node(node: &::gltf::Node, parent: Matrix, state: &mut ImportState) {
+    let world = to_matrix(node.transform().matrix()) * parent;
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != Mode::Triangles {
+                state.warnings.push(format!("skipping primitive of mesh '{}', only triangles are supported", mesh.name().unwrap_or("")));
+                continue;
+            }
+
+            let buffers = &state.buffers;
+            let warnings = &mut state.warnings;
+            let resource = state.meshes.entry((mesh.index(), primitive.index()))
+                .or_insert_with(|| read_primitive(&primitive, buffers, warnings).map(|mesh_data| {
+                    let name = format!("{} primitive {}", mesh.name().unwrap_or("mesh"), primitive.index());
+                    Arc::new(MeshResource::with_name(mesh_data, name))
+                }));
+
+            if let Some(resource) = resource {
+                let material = convert_material(&primitive.material());
+                state.scene_objects.push(SceneObject::instance(resource.clone(), material, world));
+            }
+        }
+    }
+
+    if let Some(light) = node.light() {
+        let position = Vector::vec3(0.0, 0.0, 0.0) * world;
+        let direction = (Vector::vec3(0.0, 0.0, -1.0) * world - position).vec3_normalize();
+        let [r, g, b] = light.color();
+        let color = Vector::vec3(r, g, b);
+        let range = light.range().unwrap_or(f32::INFINITY);
+
+        match light.kind() {
+            Kind::Directional => {
+                state.lights.push(Lights::Directional(DirectionalLight::new(direction, light.intensity(), color)));
+            },
+            kind => {
+                if let Kind::Spot { .. } = kind {
+                    state.warnings.push(format!("spot light '{}' is imported as a point light", light.name().unwrap_or("")));
+                }
+
+                // glTF intensities are in candela, point lights here are divided by the full sphere
+                let brightness = light.intensity() * 4.0 * consts::PI;
+                state.lights.push(Lights::Point(PointLight::new(position, brightness, color, range, Vector::vec3(0.0, 0.0, 1.0))));
+            }
+        }
+    }
+
+    if let Some(camera) = node.camera() {
+        if state.camera.is_none() {
+            let position = Vector::vec3(0.0, 0.0, 0.0) * world;
+            let target = Vector::vec3(0.0, 0.0, -1.0) * world;
+
+            match camera.projection() {
+                Projection::Perspective(perspective) => {
+                    state.camera = Some(Camera::with_fov(position, target, perspective.yfov()));
+                },
+                Projection::Orthographic(orthographic) => {
+                    // xmag is half the width of the view
+                    state.camera = Some(Camera::orthographic(position, target, orthographic.xmag() * 2.0));
+                }
+            }
+        }
+    }
+
+    for child in node.children() {
+        visit_node(&child, world, state);
+    }
+}
+
+// glTF matrices are column major for column vectors, transposing gives the row vector
+// convention used by Matrix so each glTF column becomes a row
+fn to_matrix(m: [[f32; 4]; 4]) -> Matrix {
+    Matrix::from_vector(
+        Vector::vec4(m[0][0], m[0][1], m[0][2], m[0][3]),
+        Vector::vec4(m[1][0], m[1][1], m[1][2], m[1][3]),
+        Vector::vec4(m[2][0], m[2][1], m[2][2], m[2][3]),
+        Vector::vec4(m[3][0], m[3][1], m[3][2], m[3][3])
+    )
+}
+
+fn read_primitive(primitive: &::gltf::Primitive, buffers: &[Vec<u8>], warnings: &mut Vec<String>) -> Option<Mesh> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+
+    let positions: Vec<Vector> = match reader.read_positions() {
+        Some(positions) => positions.map(|[x, y, z]| Vector::vec3(x, y, z)).collect(),
+        None => return None
+    };
+
+    let mut indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..positions.len() as u32).collect()
+    };
+
+    if indices.iter().any(|&index| index as usize >= positions.len()) {
+        warnings.push("skipping primitive with out of range vertex indices".to_string());
+        return None;
+    }
+
+    indices.truncate(indices.len() / 3 * 3);
+
+    let normals: Vec<Vector> = match reader.read_normals() {
+        Some(normals) => normals.map(|[x, y, z]| Vector::vec3(x, y, z)).collect(),
+        None => generate_normals(&positions, &indices)
+    };
+
+    let texture_coords: Vec<Vector> = match reader.read_tex_coords(0) {
+        Some(texture_coords) => texture_coords.into_f32().map(|[u, v]| Vector::vec2(u, v)).collect(),
+        None => Vec::new()
+    };
+
+    let colors: Vec<Vector> = match reader.read_colors(0) {
+        Some(colors) => colors.into_rgb_f32().map(|[r, g, b]| Vector::vec3(r, g, b)).collect(),
+        None => Vec::new()
+    };
+
+    let mut vertices = Vec::with_capacity(positions.len());
+    for i in 0..positions.len() {
+        let texture_coord = if i < texture_coords.len() {texture_coords[i]} else {Vector::vec2(0.0, 0.0)};
+        vertices.push(Vertex::with_texture_coord(positions[i], normals[i], texture_coord));
+    }
+
+    let num_tris = (indices.len() / 3) as u32;
+    if num_tris == 0 {
+        return None;
+    }
+
+    Some(Mesh {
+        vertices: vertices,
+        indices: indices,
+        num_tris: num_tris,
+        colors: colors
+    })
+}
+
+/// Maps the metallic-roughness model onto Material, metals move their base color into the
+/// specular reflectance the same way the built in metal materials are set up.
+fn convert_material(material: &::gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let base_color = Vector::vec3(r, g, b);
+    let metalness = clamp(pbr.metallic_factor(), 0.0, 1.0);
+    let roughness = clamp(pbr.roughness_factor(), 0.0, 1.0);
+    let ior = material.ior().unwrap_or(1.5);
+    let transmission = match material.transmission() {
+        Some(transmission) => transmission.transmission_factor(),
+        None => 0.0
+    };
+
+    let f0 = ((ior - 1.0) / (ior + 1.0)).powi(2);
+    let dielectric_specular = Vector::vec3(f0, f0, f0);
+
+    let albedo = base_color * (1.0 - metalness);
+    let specular = dielectric_specular * (1.0 - metalness) + base_color * metalness;
+
+    Material::new(albedo, specular, roughness, ior, transmission, metalness)
+}
+
+fn frame_objects(scene_objects: &[SceneObject]) -> Camera {
+    let mut bounds = BoundingBox::new();
+    for object in scene_objects {
+        bounds = bounds.union(object.bounding_box);
+    }
+
+    let center = (bounds.min() + bounds.max()) * 0.5;
+    let radius = (bounds.diagonal() * 0.5).vec3_length_f32().max(0.001);
+    let fov = degree_to_radians(40.0);
+    let distance = radius / (fov * 0.5).sin();
+
+    Camera::with_fov(center + Vector::vec3(0.0, 0.0, distance), center, fov)
+}
+
+// external buffers are added to the fingerprint by their uri and contents
+fn load_buffers(document: &::gltf::Document, mut blob: Option<Vec<u8>>, base_dir: &Path, fingerprint: &mut Fingerprint) -> Result<Vec<Vec<u8>>, GltfError> {
+    let mut buffers = Vec::new();
+
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            Source::Bin => match blob.take() {
+                Some(data) => data,
+                None => return Err(GltfError::Buffer("binary buffer is missing from the glb file".to_string()))
+            },
+            Source::Uri(uri) => {
+                if uri.starts_with("data:") {
+                    let encoded = match uri.find(";base64,") {
+                        Some(start) => &uri[start + 8..],
+                        None => return Err(GltfError::Buffer("only base64 data uris are supported".to_string()))
+                    };
+
+                    match base64::decode(encoded) {
+                        Ok(data) => data,
+                        Err(error) => return Err(GltfError::Buffer(format!("invalid base64 buffer: {}", error)))
+                    }
+                } else {
+                    let data = fs::read(base_dir.join(decode_uri(uri)))?;
+                    fingerprint.add(uri.as_bytes());
+                    fingerprint.add(&data);
+                    data
+                }
+            }
+        };
+
+        if data.len() < buffer.length() {
+            return Err(GltfError::Buffer(format!("buffer {} is {} bytes, expected {}", buffer.index(), data.len(), buffer.length())));
+        }
+
+        buffers.push(data);
+    }
+
+    Ok(buffers)
+}
+
+fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Ok(hex) = std::str::from_utf8(&bytes[i + 1..i + 3]) {
+                if let Ok(value) = u8::from_str_radix(hex, 16) {
+                    decoded.push(value);
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,404 @@
+use crate::geometry::{Mesh, Vertex, triangulate};
+use crate::shading::materials::Material;
+use crate::vector_simd::Vector;
+use crate::math::clamp;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(error) => write!(f, "{}", error),
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(error: io::Error) -> Self {
+        ObjError::Io(error)
+    }
+}
+
+/// A single group of faces sharing one material. Every `o`, `g` and `usemtl`
+/// statement in the file starts a new one.
+pub struct ObjObject {
+    pub name: String,
+    pub mesh: Mesh,
+    pub material: Material
+}
+
+/// The objects of an obj file and the problems that were skipped while loading it, like a
+/// missing material library.
+pub struct ObjFile {
+    pub objects: Vec<ObjObject>,
+    pub warnings: Vec<String>
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<ObjFile, ObjError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    parse_obj(&source, base_dir)
+}
+
+pub fn parse_obj(source: &str, base_dir: &Path) -> Result<ObjFile, ObjError> {
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut texture_coords = Vec::new();
+    let mut materials = HashMap::new();
+
+    let mut objects = Vec::new();
+    let mut warnings = Vec::new();
+    let mut object_name = String::from("default");
+    let mut group_name = String::new();
+    let mut material_name = String::new();
+    let mut builder = ObjectBuilder::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+
+        match keyword {
+            "v" => positions.push(parse_vector(&mut parts, 3, line_number)?),
+            "vn" => normals.push(parse_vector(&mut parts, 3, line_number)?),
+            "vt" => texture_coords.push(parse_vector(&mut parts, 2, line_number)?),
+            "f" => {
+                let mut face = Vec::new();
+                for part in parts {
+                    face.push(parse_face_vertex(part, positions.len(), texture_coords.len(), normals.len(), line_number)?);
+                }
+
+                if face.len() < 3 {
+                    return Err(ObjError::Parse { line: line_number, message: "a face needs at least 3 vertices".to_string() });
+                }
+
+                builder.add_face(&face, &positions, &texture_coords, &normals);
+            },
+            "o" | "g" | "usemtl" => {
+                let name = parts.collect::<Vec<_>>().join(" ");
+
+                if !builder.is_empty() {
+                    let finished = std::mem::replace(&mut builder, ObjectBuilder::new());
+                    objects.push(finished.build(object_name.clone(), &group_name, &material_name, &materials, &mut warnings));
+                }
+
+                match keyword {
+                    "o" => {
+                        object_name = name;
+                        group_name.clear();
+                    },
+                    "g" => group_name = name,
+                    _ => material_name = name
+                }
+            },
+            "mtllib" => {
+                for file in parts {
+                    match load_mtl(base_dir.join(file)) {
+                        Ok(library) => materials.extend(library),
+                        Err(error) => warnings.push(format!("could not load material library '{}': {}", file, error))
+                    }
+                }
+            },
+            // smoothing groups, lines and points are not supported and ignored
+            _ => ()
+        }
+    }
+
+    if !builder.is_empty() {
+        objects.push(builder.build(object_name, &group_name, &material_name, &materials, &mut warnings));
+    }
+
+    Ok(ObjFile {
+        objects: objects,
+        warnings: warnings
+    })
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Material>, ObjError> {
+    let source = fs::read_to_string(path)?;
+    parse_mtl(&source)
+}
+
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+
+        let mut parts = line.split_whitespace();
+        let keyword = match parts.next() {
+            Some(keyword) => keyword,
+            None => continue
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material.to_material());
+            }
+            current = Some((parts.collect::<Vec<_>>().join(" "), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => continue
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = Some(parse_vector(&mut parts, 3, line_number)?),
+            "Ks" => material.specular = Some(parse_vector(&mut parts, 3, line_number)?),
+            "Ns" => material.shininess = Some(parse_float(parts.next(), line_number)?),
+            "Ni" => material.ior = Some(parse_float(parts.next(), line_number)?),
+            "d" => material.dissolve = Some(parse_float(parts.next(), line_number)?),
+            "Tr" => material.dissolve = Some(1.0 - parse_float(parts.next(), line_number)?),
+            "Pr" => material.roughness = Some(parse_float(parts.next(), line_number)?),
+            "Pm" => material.metalness = Some(parse_float(parts.next(), line_number)?),
+            _ => ()
+        }
+    }
+
+    if let Some((name, material)) = current.take() {
+        materials.insert(name, material.to_material());
+    }
+
+    Ok(materials)
+}
+
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse: Option<Vector>,
+    specular: Option<Vector>,
+    shininess: Option<f32>,
+    ior: Option<f32>,
+    dissolve: Option<f32>,
+    roughness: Option<f32>,
+    metalness: Option<f32>
+}
+
+impl MtlMaterial {
+    fn to_material(&self) -> Material {
+        let mut material = Material::default();
+
+        if let Some(diffuse) = self.diffuse {
+            material.albedo = diffuse;
+        }
+
+        // Ks is treated as a specular level where 0.5 maps to the usual dielectric reflectance of 0.04
+        if let Some(specular) = self.specular {
+            material.specular = specular * 0.08;
+        }
+
+        if let Some(roughness) = self.roughness {
+            material.roughness = roughness;
+        } else if let Some(shininess) = self.shininess {
+            material.roughness = (2.0 / (shininess.max(0.0) + 2.0)).sqrt();
+        }
+
+        if let Some(ior) = self.ior {
+            material.ior = ior;
+        }
+
+        if let Some(dissolve) = self.dissolve {
+            material.transmission = 1.0 - clamp(dissolve, 0.0, 1.0);
+        }
+
+        if let Some(metalness) = self.metalness {
+            let metalness = clamp(metalness, 0.0, 1.0);
+            material.specular = material.specular * (1.0 - metalness) + material.albedo * metalness;
+            material.albedo *= 1.0 - metalness;
+            material.metalicness = metalness;
+        }
+
+        material
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    position: usize,
+    texture_coord: Option<usize>,
+    normal: Option<usize>
+}
+
+struct ObjectBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    vertex_lookup: HashMap<FaceVertex, u32>,
+    generated_normals: HashMap<usize, Vector>,
+    vertex_positions: Vec<Option<usize>>
+}
+
+impl ObjectBuilder {
+    fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            vertex_lookup: HashMap::new(),
+            generated_normals: HashMap::new(),
+            vertex_positions: Vec::new()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn add_face(&mut self, face: &[FaceVertex], positions: &[Vector], texture_coords: &[Vector], normals: &[Vector]) {
+        let polygon: Vec<Vector> = face.iter().map(|v| positions[v.position]).collect();
+
+        for triangle in triangulate(&polygon) {
+            let a = polygon[triangle[0]];
+            let b = polygon[triangle[1]];
+            let c = polygon[triangle[2]];
+            // not normalized so larger faces contribute more to the smoothed normal
+            let face_normal = (b - a).vec3_cross(c - a);
+
+            for &corner in &triangle {
+                let face_vertex = face[corner];
+
+                if face_vertex.normal.is_none() {
+                    let sum = self.generated_normals.entry(face_vertex.position).or_insert_with(|| Vector::vec3(0.0, 0.0, 0.0));
+                    *sum += face_normal;
+                }
+
+                let index = match self.vertex_lookup.get(&face_vertex) {
+                    Some(&index) => index,
+                    None => {
+                        let index = self.vertices.len() as u32;
+                        let normal = match face_vertex.normal {
+                            Some(normal) => normals[normal],
+                            None => Vector::vec3(0.0, 0.0, 0.0)
+                        };
+                        let texture_coord = match face_vertex.texture_coord {
+                            Some(texture_coord) => texture_coords[texture_coord],
+                            None => Vector::vec2(0.0, 0.0)
+                        };
+
+                        self.vertices.push(Vertex::with_texture_coord(positions[face_vertex.position], normal, texture_coord));
+                        self.vertex_positions.push(if face_vertex.normal.is_none() {Some(face_vertex.position)} else {None});
+                        self.vertex_lookup.insert(face_vertex, index);
+                        index
+                    }
+                };
+
+                self.indices.push(index);
+            }
+        }
+    }
+
+    fn build(mut self, object_name: String, group_name: &str, material_name: &str, materials: &HashMap<String, Material>, warnings: &mut Vec<String>) -> ObjObject {
+        for i in 0..self.vertices.len() {
+            if let Some(position) = self.vertex_positions[i] {
+                let normal = self.generated_normals[&position];
+                self.vertices[i].normal = if normal.vec3_dot_f32(normal) > 0.0 {normal.vec3_normalize()} else {Vector::vec3(0.0, 1.0, 0.0)};
+            }
+        }
+
+        let material = match materials.get(material_name) {
+            Some(material) => *material,
+            None => {
+                if !material_name.is_empty() {
+                    warnings.push(format!("material '{}' is not defined, using the default material", material_name));
+                }
+                Material::default()
+            }
+        };
+
+        let mut name = object_name;
+        if !group_name.is_empty() {
+            name = format!("{}/{}", name, group_name);
+        }
+        if !material_name.is_empty() {
+            name = format!("{}[{}]", name, material_name);
+        }
+
+        let num_tris = (self.indices.len() / 3) as u32;
+
+        ObjObject {
+            name: name,
+            mesh: Mesh {
+                vertices: self.vertices,
+                indices: self.indices,
+                num_tris: num_tris,
+                colors: Vec::new()
+            },
+            material: material
+        }
+    }
+}
+
+fn parse_float(value: Option<&str>, line: usize) -> Result<f32, ObjError> {
+    match value {
+        Some(value) => value.parse::<f32>().map_err(|_| ObjError::Parse { line: line, message: format!("expected a number, found '{}'", value) }),
+        None => Err(ObjError::Parse { line: line, message: "expected a number".to_string() })
+    }
+}
+
+fn parse_vector<'a, I: Iterator<Item = &'a str>>(parts: &mut I, components: usize, line: usize) -> Result<Vector, ObjError> {
+    let mut values = [0.0; 3];
+    for value in values.iter_mut().take(components) {
+        *value = parse_float(parts.next(), line)?;
+    }
+    Ok(Vector::vec3(values[0], values[1], values[2]))
+}
+
+fn parse_face_vertex(part: &str, num_positions: usize, num_texture_coords: usize, num_normals: usize, line: usize) -> Result<FaceVertex, ObjError> {
+    let mut indices = part.split('/');
+
+    let position = resolve_index(indices.next(), num_positions, "vertex", line)?;
+    let texture_coord = match indices.next() {
+        Some(index) if !index.is_empty() => Some(resolve_index(Some(index), num_texture_coords, "texture coordinate", line)?),
+        _ => None
+    };
+    let normal = match indices.next() {
+        Some(index) if !index.is_empty() => Some(resolve_index(Some(index), num_normals, "normal", line)?),
+        _ => None
+    };
+
+    Ok(FaceVertex {
+        position: position,
+        texture_coord: texture_coord,
+        normal: normal
+    })
+}
+
+/// OBJ indices start at 1, negative values count backwards from the last element read so far.
+fn resolve_index(index: Option<&str>, count: usize, kind: &str, line: usize) -> Result<usize, ObjError> {
+    let index = index.unwrap_or("");
+    let value = match index.parse::<i64>() {
+        Ok(value) => value,
+        Err(_) => return Err(ObjError::Parse { line: line, message: format!("invalid {} index '{}'", kind, index) })
+    };
+
+    let resolved = if value > 0 {value - 1} else {count as i64 + value};
+
+    if value == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ObjError::Parse { line: line, message: format!("{} index {} is out of range", kind, value) });
+    }
+
+    Ok(resolved as usize)
+}
@@ -0,0 +1,527 @@
+use crate::geometry::{Mesh, Vertex, triangulate, generate_normals};
+use crate::vector_simd::Vector;
+
+use std::path::Path;
+use std::{fmt, fs, io, str};
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    Header { line: usize, message: String },
+    Data(String)
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::Io(error) => write!(f, "{}", error),
+            PlyError::Header { line, message } => write!(f, "header line {}: {}", line, message),
+            PlyError::Data(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl From<io::Error> for PlyError {
+    fn from(error: io::Error) -> Self {
+        PlyError::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8
+        }
+    }
+
+    // scale that maps integer color channels to [0, 1]
+    fn color_scale(self) -> f32 {
+        match self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List(ScalarType, ScalarType)
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    property_type: PropertyType
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexSlot {
+    PositionX,
+    PositionY,
+    PositionZ,
+    NormalX,
+    NormalY,
+    NormalZ,
+    Red,
+    Green,
+    Blue,
+    U,
+    V,
+    Ignored
+}
+
+impl VertexSlot {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "x" => VertexSlot::PositionX,
+            "y" => VertexSlot::PositionY,
+            "z" => VertexSlot::PositionZ,
+            "nx" => VertexSlot::NormalX,
+            "ny" => VertexSlot::NormalY,
+            "nz" => VertexSlot::NormalZ,
+            "red" | "r" | "diffuse_red" => VertexSlot::Red,
+            "green" | "g" | "diffuse_green" => VertexSlot::Green,
+            "blue" | "b" | "diffuse_blue" => VertexSlot::Blue,
+            "u" | "s" | "texture_u" | "texture_s" => VertexSlot::U,
+            "v" | "t" | "texture_v" | "texture_t" => VertexSlot::V,
+            _ => VertexSlot::Ignored
+        }
+    }
+}
+
+pub fn load_ply<P: AsRef<Path>>(path: P) -> Result<Mesh, PlyError> {
+    let data = fs::read(path)?;
+    parse_ply(&data)
+}
+
+pub fn parse_ply(data: &[u8]) -> Result<Mesh, PlyError> {
+    let (format, elements, body_offset) = parse_header(data)?;
+    let body = &data[body_offset..];
+
+    let mut reader = match format {
+        Format::Ascii => {
+            let text = match str::from_utf8(body) {
+                Ok(text) => text,
+                Err(_) => return Err(PlyError::Data("ascii body is not valid text".to_string()))
+            };
+            Reader::Ascii { tokens: text.split_whitespace(), size: text.len() }
+        },
+        Format::BinaryLittleEndian => Reader::Binary { data: body, offset: 0, big_endian: false },
+        Format::BinaryBigEndian => Reader::Binary { data: body, offset: 0, big_endian: true }
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut texture_coords = Vec::new();
+    let mut indices = Vec::new();
+    let mut has_vertices = false;
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => {
+                has_vertices = true;
+                read_vertices(&mut reader, element, &mut positions, &mut normals, &mut colors, &mut texture_coords)?;
+            },
+            "face" => {
+                if !has_vertices {
+                    return Err(PlyError::Data("the face element has to follow the vertex element".to_string()));
+                }
+                read_faces(&mut reader, element, &positions, &mut indices)?;
+            },
+            _ => skip_element(&mut reader, element)?
+        }
+    }
+
+    if !has_vertices {
+        return Err(PlyError::Data("file has no vertex element".to_string()));
+    }
+
+    if normals.is_empty() {
+        normals = generate_normals(&positions, &indices);
+    }
+
+    let mut vertices = Vec::with_capacity(positions.len());
+    for i in 0..positions.len() {
+        let texture_coord = if texture_coords.is_empty() {Vector::vec2(0.0, 0.0)} else {texture_coords[i]};
+        vertices.push(Vertex::with_texture_coord(positions[i], normals[i], texture_coord));
+    }
+
+    let num_tris = (indices.len() / 3) as u32;
+
+    Ok(Mesh {
+        vertices: vertices,
+        indices: indices,
+        num_tris: num_tris,
+        colors: colors
+    })
+}
+
+fn parse_header(data: &[u8]) -> Result<(Format, Vec<Element>, usize), PlyError> {
+    let mut offset = 0;
+    let mut line_number = 0;
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    loop {
+        let end = match data[offset..].iter().position(|&c| c == b'\n') {
+            Some(end) => offset + end,
+            None => return Err(PlyError::Header { line: line_number + 1, message: "missing 'end_header'".to_string() })
+        };
+
+        line_number += 1;
+        let line = match str::from_utf8(&data[offset..end]) {
+            Ok(line) => line.trim(),
+            Err(_) => return Err(PlyError::Header { line: line_number, message: "header is not valid text".to_string() })
+        };
+        offset = end + 1;
+
+        let header_error = |message: String| PlyError::Header { line: line_number, message: message };
+
+        if line_number == 1 {
+            if line != "ply" {
+                return Err(header_error("not a ply file, expected 'ply' as the first line".to_string()));
+            }
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+
+        match parts[0] {
+            "format" => {
+                if parts.len() != 3 {
+                    return Err(header_error("expected 'format <type> <version>'".to_string()));
+                }
+                if parts[2] != "1.0" {
+                    return Err(header_error(format!("unsupported format version '{}'", parts[2])));
+                }
+                format = match parts[1] {
+                    "ascii" => Some(Format::Ascii),
+                    "binary_little_endian" => Some(Format::BinaryLittleEndian),
+                    "binary_big_endian" => Some(Format::BinaryBigEndian),
+                    other => return Err(header_error(format!("unsupported format '{}'", other)))
+                };
+            },
+            "comment" | "obj_info" => (),
+            "element" => {
+                if parts.len() != 3 {
+                    return Err(header_error("expected 'element <name> <count>'".to_string()));
+                }
+                let count = match parts[2].parse::<usize>() {
+                    Ok(count) => count,
+                    Err(_) => return Err(header_error(format!("invalid element count '{}'", parts[2])))
+                };
+                elements.push(Element { name: parts[1].to_string(), count: count, properties: Vec::new() });
+            },
+            "property" => {
+                let element = match elements.last_mut() {
+                    Some(element) => element,
+                    None => return Err(header_error("property declared before any element".to_string()))
+                };
+
+                let parse_type = |name: &str| match ScalarType::parse(name) {
+                    Some(scalar) => Ok(scalar),
+                    None => Err(PlyError::Header { line: line_number, message: format!("unsupported property type '{}'", name) })
+                };
+
+                let property = if parts.len() == 5 && parts[1] == "list" {
+                    let count_type = parse_type(parts[2])?;
+                    if count_type == ScalarType::Float32 || count_type == ScalarType::Float64 {
+                        return Err(header_error(format!("list count type has to be an integer, found '{}'", parts[2])));
+                    }
+                    Property { name: parts[4].to_string(), property_type: PropertyType::List(count_type, parse_type(parts[3])?) }
+                } else if parts.len() == 3 {
+                    Property { name: parts[2].to_string(), property_type: PropertyType::Scalar(parse_type(parts[1])?) }
+                } else {
+                    return Err(header_error("expected 'property <type> <name>' or 'property list <count type> <type> <name>'".to_string()));
+                };
+
+                element.properties.push(property);
+            },
+            "end_header" => break,
+            other => return Err(header_error(format!("unknown header keyword '{}'", other)))
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements, offset)),
+        None => Err(PlyError::Header { line: line_number, message: "missing 'format' line".to_string() })
+    }
+}
+
+enum Reader<'a> {
+    // `size` is the length of the whole body, the bytes left are never more
+    Ascii { tokens: str::SplitWhitespace<'a>, size: usize },
+    Binary { data: &'a [u8], offset: usize, big_endian: bool }
+}
+
+impl<'a> Reader<'a> {
+    // the element counts come from the file, every element takes at least one byte so a
+    // count larger than the bytes left can't be right and isn't worth reserving memory for
+    fn reserve_count(&self, count: usize) -> usize {
+        match self {
+            Reader::Ascii { size, .. } => count.min(*size),
+            Reader::Binary { data, offset, .. } => count.min(data.len().saturating_sub(*offset))
+        }
+    }
+
+    fn read(&mut self, scalar: ScalarType) -> Result<f64, PlyError> {
+        match self {
+            Reader::Ascii { tokens, .. } => {
+                let token = match tokens.next() {
+                    Some(token) => token,
+                    None => return Err(PlyError::Data("unexpected end of file".to_string()))
+                };
+                match token.parse::<f64>() {
+                    Ok(value) => Ok(value),
+                    Err(_) => Err(PlyError::Data(format!("expected a number, found '{}'", token)))
+                }
+            },
+            Reader::Binary { data, offset, big_endian } => {
+                let size = scalar.size();
+                if *offset + size > data.len() {
+                    return Err(PlyError::Data("unexpected end of file".to_string()));
+                }
+
+                let mut bytes = [0u8; 8];
+                bytes[..size].copy_from_slice(&data[*offset..*offset + size]);
+                *offset += size;
+
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+
+                let value = match scalar {
+                    ScalarType::Int8 => bytes[0] as i8 as f64,
+                    ScalarType::UInt8 => bytes[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::UInt32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes(bytes)
+                };
+
+                Ok(value)
+            }
+        }
+    }
+
+    fn skip_property(&mut self, property_type: PropertyType) -> Result<(), PlyError> {
+        match property_type {
+            PropertyType::Scalar(scalar) => {
+                self.read(scalar)?;
+            },
+            PropertyType::List(count_type, item_type) => {
+                let count = self.read(count_type)? as usize;
+                for _ in 0..count {
+                    self.read(item_type)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_vertices(reader: &mut Reader, element: &Element, positions: &mut Vec<Vector>, normals: &mut Vec<Vector>, colors: &mut Vec<Vector>, texture_coords: &mut Vec<Vector>) -> Result<(), PlyError> {
+    let mut slots = Vec::new();
+    for property in &element.properties {
+        let slot = VertexSlot::from_name(&property.name);
+        match property.property_type {
+            PropertyType::List(..) if slot != VertexSlot::Ignored => {
+                return Err(PlyError::Data(format!("vertex property '{}' is a list, only scalar values are supported", property.name)));
+            },
+            _ => slots.push(slot)
+        }
+    }
+
+    let has = |wanted: &[VertexSlot]| wanted.iter().all(|slot| slots.contains(slot));
+
+    if !has(&[VertexSlot::PositionX, VertexSlot::PositionY, VertexSlot::PositionZ]) {
+        return Err(PlyError::Data("vertex element needs 'x', 'y' and 'z' properties".to_string()));
+    }
+
+    let has_normals = has(&[VertexSlot::NormalX, VertexSlot::NormalY, VertexSlot::NormalZ]);
+    let has_colors = has(&[VertexSlot::Red, VertexSlot::Green, VertexSlot::Blue]);
+    let has_texture_coords = has(&[VertexSlot::U, VertexSlot::V]);
+
+    let reserve_count = reader.reserve_count(element.count);
+    positions.reserve(reserve_count);
+    if has_normals {
+        normals.reserve(reserve_count);
+    }
+    if has_colors {
+        colors.reserve(reserve_count);
+    }
+    if has_texture_coords {
+        texture_coords.reserve(reserve_count);
+    }
+
+    for _ in 0..element.count {
+        let mut values = [0.0f32; 11];
+
+        for (property, &slot) in element.properties.iter().zip(slots.iter()) {
+            match (slot, property.property_type) {
+                (VertexSlot::Ignored, property_type) => reader.skip_property(property_type)?,
+                (slot, PropertyType::Scalar(scalar)) => {
+                    let mut value = reader.read(scalar)? as f32;
+                    if slot == VertexSlot::Red || slot == VertexSlot::Green || slot == VertexSlot::Blue {
+                        value *= scalar.color_scale();
+                    }
+                    values[slot as usize] = value;
+                },
+                (_, PropertyType::List(..)) => unreachable!()
+            }
+        }
+
+        positions.push(Vector::vec3(values[VertexSlot::PositionX as usize], values[VertexSlot::PositionY as usize], values[VertexSlot::PositionZ as usize]));
+
+        if has_normals {
+            normals.push(Vector::vec3(values[VertexSlot::NormalX as usize], values[VertexSlot::NormalY as usize], values[VertexSlot::NormalZ as usize]));
+        }
+
+        if has_colors {
+            colors.push(Vector::vec3(values[VertexSlot::Red as usize], values[VertexSlot::Green as usize], values[VertexSlot::Blue as usize]));
+        }
+
+        if has_texture_coords {
+            texture_coords.push(Vector::vec2(values[VertexSlot::U as usize], values[VertexSlot::V as usize]));
+        }
+    }
+
+    Ok(())
+}
+
+fn read_faces(reader: &mut Reader, element: &Element, positions: &[Vector], indices: &mut Vec<u32>) -> Result<(), PlyError> {
+    let index_property = element.properties.iter().position(|property| property.name == "vertex_indices" || property.name == "vertex_index");
+
+    let index_property = match index_property {
+        Some(index) => index,
+        None => return Err(PlyError::Data("face element needs a 'vertex_indices' list property".to_string()))
+    };
+
+    let (count_type, item_type) = match element.properties[index_property].property_type {
+        PropertyType::List(count_type, item_type) => (count_type, item_type),
+        PropertyType::Scalar(_) => return Err(PlyError::Data("face property 'vertex_indices' has to be a list".to_string()))
+    };
+
+    indices.reserve(reader.reserve_count(element.count) * 3);
+    let mut face = Vec::new();
+
+    for face_index in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            if i != index_property {
+                reader.skip_property(property.property_type)?;
+                continue;
+            }
+
+            face.clear();
+            let count = reader.read(count_type)? as usize;
+            for _ in 0..count {
+                let index = reader.read(item_type)?;
+                if index < 0.0 || index as usize >= positions.len() {
+                    return Err(PlyError::Data(format!("face {} references vertex {} but there are only {} vertices", face_index, index, positions.len())));
+                }
+                face.push(index as u32);
+            }
+        }
+
+        if face.len() < 3 {
+            continue;
+        }
+
+        if face.len() == 3 {
+            indices.extend_from_slice(&face);
+        } else {
+            let polygon: Vec<Vector> = face.iter().map(|&index| positions[index as usize]).collect();
+            for triangle in triangulate(&polygon) {
+                indices.push(face[triangle[0]]);
+                indices.push(face[triangle[1]]);
+                indices.push(face[triangle[2]]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn skip_element(reader: &mut Reader, element: &Element) -> Result<(), PlyError> {
+    if let Reader::Binary { data, offset, .. } = reader {
+        // fixed size elements can be skipped without decoding them
+        let mut size = 0;
+        let mut fixed_size = true;
+        for property in &element.properties {
+            match property.property_type {
+                PropertyType::Scalar(scalar) => size += scalar.size(),
+                PropertyType::List(..) => fixed_size = false
+            }
+        }
+
+        if fixed_size {
+            let end = size.checked_mul(element.count).and_then(|element_size| offset.checked_add(element_size));
+            return match end {
+                Some(end) if end <= data.len() => {
+                    *offset = end;
+                    Ok(())
+                },
+                _ => Err(PlyError::Data(format!("element '{}' runs past the end of the file", element.name)))
+            };
+        }
+    }
+
+    for _ in 0..element.count {
+        for property in &element.properties {
+            reader.skip_property(property.property_type)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,185 +1,154 @@
-mod vector_simd;
-mod matrix;
-mod geometry;
-mod shading;
-mod ray_tracer;
-mod scene;
-mod math;
-mod test_scenes;
-mod bvh;
-mod camera;
+#![allow(clippy::redundant_field_names)]
 
-use scene::*;
-use test_scenes::*;
-use vector_simd::Vector;
-use std::time::Instant;
-use ray_tracer::{RayType, cast_ray};
-use std::{f32, f32::consts, fmt};
-use image;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::cell::UnsafeCell;
+mod cli;
 
-pub struct UnsafeRgbaImage(UnsafeCell<image::RgbImage>);
+use ray_tracer::{RenderBuffers, RenderSettings, Renderer, SceneData};
+use ray_tracer::camera::ProjectionType;
+use ray_tracer::checkpoint::Checkpoint;
+use ray_tracer::renderer::Snapshot;
+use ray_tracer::scene_file::load_scene_file_with;
+use ray_tracer::loaders::gltf::load_gltf_with_settings;
+use ray_tracer::test_scenes::{TEST_SCENES, load_test_scene};
+use cli::{Command, Options, SceneSource};
+use std::path::Path;
+use std::process;
 
-impl UnsafeRgbaImage {
-    pub fn new(img: image::RgbImage) -> Self {
-        Self(UnsafeCell::new(img))
-    }
+fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Render(options)) => options,
+        Ok(Command::ListScenes) => {
+            for (name, _, description) in TEST_SCENES.iter() {
+                println!("{:<16}{}", name, description);
+            }
+            return;
+        },
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        },
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            process::exit(2);
+        }
+    };
 
-    pub fn as_ref(&self) -> &image::RgbImage {
-        unsafe { self.0.get().as_ref() }.unwrap()
+    let (mut scene, mut settings) = load_scene(&options);
+    for warning in scene.warnings.iter() {
+        eprintln!("warning: {}", warning);
     }
-
-    pub fn put_pixel(&self, x: u32, y: u32, pixel: image::Rgb<u8>) {
-        unsafe { self.0.get().as_mut() }
-            .unwrap()
-            .put_pixel(x, y, pixel)
+    if options.bvh_stats {
+        println!("{}", scene.bvh_stats);
     }
-}
-
-unsafe impl Sync for UnsafeRgbaImage {}
-
-#[derive(Clone, Copy, Debug)]
-pub struct RenderSettings {
-    pub width:u32,
-    pub height:u32,
-    pub max_ray_depth: u32,
-    pub diffuse_samples: u32,
-    pub specular_samples: u32,
-    pub aa_samples: u32,
-    pub background_color: Vector
-}
-
-impl RenderSettings {
-    fn new(width: u32, height: u32, ray_depth: u32, diffuse_samples: u32, specular_samples: u32, aa_samples: u32, background_color: Vector) -> Self {
-        Self {width: width, height: height, max_ray_depth: ray_depth, diffuse_samples: diffuse_samples, specular_samples: specular_samples, aa_samples: aa_samples, background_color: background_color}
-    }
-}
-
-pub struct Stats {
-    pub num_rays_shot: u128,
-    pub num_tringle_tests: u128,
-    pub num_triangles_intersected: u128,
-    pub render_time: f64
-}
-
-impl Default for Stats {
-    fn default() -> Stats {
-        Stats {
-            num_rays_shot: 0,
-            num_tringle_tests: 0,
-            num_triangles_intersected: 0,
-            render_time: 0.0
+    let checkpoint = options.resume.as_ref().map(|path| match Checkpoint::load(path) {
+        Ok(checkpoint) => checkpoint,
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
         }
+    });
+    if let Some(ref checkpoint) = checkpoint {
+        settings = checkpoint.settings;
+        println!("resuming after {} passes", checkpoint.passes);
     }
-}
-
-impl fmt::Display for Stats {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-            "number of rays shot: {},\n number of triangles tested: {},\n number of triangles intersected: {},\n image generated in: {}",
-            self.num_rays_shot, self.num_tringle_tests, self.num_triangles_intersected, self.render_time
-        )
-    }
-}
-
-#[derive(Copy, Clone)]
-struct RenderThreadInfo {
-    pub offset: (u32, u32),
-    pub dimensions: (u32, u32)
-}
-
-fn main() {
-    let settings = RenderSettings::new(1280, 720, 2, 4, 4, 7, Vector::vec3(0.86, 0.92, 1.0));
-    //let settings = RenderSettings::new(1280, 720, 2, 3, 0, 8, Vector::vec3(0.0, 0.0, 0.0));
-    let buffer = UnsafeRgbaImage::new(image::RgbImage::new(settings.width, settings.height));
-
-    let scene = spehres();
-
-    let max_threads = num_cpus::get();
-    println!("threads: {}", max_threads);
-
-    let mut thread_info = Vec::new();
-    for y in 0..settings.height as u32 {
-        for x in 0..settings.width as u32 {
-            thread_info.push(Vector::vec2(x as f32, y as f32));
+    options.apply(&mut settings);
+    if let Some(fov) = options.fov {
+        match scene.camera.projection {
+            ProjectionType::Perspective(ref mut perspective) => perspective.fov = fov,
+            ProjectionType::Fisheye(ref mut fisheye) => fisheye.fov = fov,
+            _ => eprintln!("warning: --fov is ignored, the camera projection has no field of view")
         }
     }
 
-    let num_render_jobs = settings.width * settings.height;
-    let render_job_counter = AtomicUsize::new(0);
-    let threads_spawned = AtomicUsize::new(0);
-
-    let now = Instant::now();
-
-    let origin = Vector::vec3(0.0, 0.0, 0.0) * scene.camera.to_world;
-    let aspect_ratio = settings.width as f32 / settings.height as f32;
-    let fov = 40.0 * (consts::PI / 180.0); 
-    let scale = (fov * 0.5).tan();
-
-    crossbeam_utils::thread::scope(|s| {
-        for _ in 0..max_threads {
-            s.spawn(|_| {   
-                let mut stats = Stats {..Default::default()};
-                let thread_num = threads_spawned.fetch_add(1, Ordering::Relaxed);
-                loop {
-                    let i = render_job_counter.fetch_add(1, Ordering::Relaxed);
-
-                    if i >= num_render_jobs as usize {
-                        break;
-                    }
-
-                    render(thread_info[i], &buffer, origin, aspect_ratio, scale, settings, &scene, &mut stats);
-
-                }
-                println!("thread: {}, num triangle intersects: {}", thread_num, stats.num_tringle_tests);
-            });       
-        };
+    let renderer = match options.threads {
+        Some(threads) => Renderer::with_threads(settings, threads),
+        None => Renderer::new(settings)
+    };
+    println!("threads: {}", renderer.threads);
+
+    // the first ctrl-c stops the render and still writes the image, a second one quits
+    let cancel = renderer.cancel.clone();
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() {
+            process::exit(130);
+        }
+        eprintln!("stopping, press ctrl-c again to quit without writing the image");
+        cancel.cancel();
     }).unwrap();
 
-    let end = now.elapsed().as_secs() as f64 + now.elapsed().subsec_nanos() as f64 * 1e-9;
-    println!("render time {}", end);
+    let checkpoint_path = options.checkpoint.as_ref().or(options.resume.as_ref());
+    let on_snapshot = |snapshot: &Snapshot| {
+        if !snapshot.last {
+            println!("pass {}", snapshot.passes);
+            write_to_file(&snapshot.buffers(), &settings, &options);
+        }
+        if let Some(path) = checkpoint_path {
+            save_checkpoint(snapshot, path);
+        }
+    };
+
+    let (buffers, stats) = match (options.progressive, checkpoint) {
+        (Some(interval), Some(checkpoint)) => match renderer.resume_progressive(&scene, &checkpoint, interval, on_snapshot) {
+            Ok(result) => result,
+            Err(error) => {
+                eprintln!("{}: {}", options.resume.as_ref().unwrap().display(), error);
+                process::exit(1);
+            }
+        },
+        (Some(interval), None) => renderer.render_progressive(&scene, interval, on_snapshot),
+        (None, _) => renderer.render(&scene)
+    };
+    println!("{}", stats);
+    if stats.stopped_early {
+        println!("render stopped early, the image is incomplete");
+    }
 
-    write_to_file(&buffer);
+    write_to_file(&buffers, &settings, &options);
 }
 
-fn render(info: Vector, buffer: & UnsafeRgbaImage, origin: Vector, aspect_ratio: f32, scale: f32, settings: RenderSettings, scene: &SceneData, stats: &mut Stats ) {
-    let a = aspect_ratio * scale;
-    let x = info.x();
-    let y = info.y();
-
-    let mut color = Vector::vec3(0.0, 0.0, 0.0);
-    let sample_points = get_aa_distribution(settings.aa_samples);
-    for i in 0..sample_points.len() {
-        let p_x = (2.0 * (x + sample_points[i].0) / settings.width as f32 - 1.0) * a; 
-        let p_y = (1.0 - 2.0 * (y + sample_points[i].1) / settings.height as f32) * scale; 
-        let dir = Vector::vec3(p_x, p_y, -1.0)  * scene.camera.to_world;
-        let ray_dir = dir - origin;
-        color += cast_ray(origin, ray_dir.vec3_normalize(), &scene, 0, settings, RayType::CameraRay, stats);
+fn load_scene(options: &Options) -> (SceneData, RenderSettings) {
+    // the command line settings are applied before the bvhs are built, they override the
+    // bvh settings of a scene file as well
+    let mut settings = RenderSettings::default();
+    options.apply(&mut settings);
+
+    let path = match options.scene {
+        SceneSource::BuiltIn(ref name) => return (load_test_scene(name, &settings.bvh).unwrap(), RenderSettings::default()),
+        SceneSource::File(ref path) => path
+    };
+
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let result = if extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb") {
+        load_gltf_with_settings(path, &settings.bvh).map(|scene| (scene, RenderSettings::default())).map_err(|error| error.to_string())
+    } else {
+        load_scene_file_with(path, |settings| options.apply(settings)).map_err(|error| error.to_string())
+    };
+
+    match result {
+        Ok(scene) => scene,
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
     }
-
-    color /= sample_points.len() as f32;
-    let output = color.clamp(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(1.0, 1.0, 1.0)) * 255.0;
-    let (r, g, b, a2) = output.into();
-    buffer.put_pixel(x as u32, y as u32, image::Rgb([r as u8, g as u8, b as u8]));
 }
 
-fn get_aa_distribution(samples: u32) -> Vec<(f32, f32)> {
-    let mut sample_pos = Vec::new();
-    let ratio = 1.0 / samples as f32;
-
-    for x in 0..samples {
-        for y in 0..samples {
-            let sample_pos_x = x as f32 * ratio + ratio * 0.5;
-            let sample_pos_y = y as f32 * ratio + ratio * 0.5;
-
-            sample_pos.push((sample_pos_x, sample_pos_y))
+fn write_to_file(buffers: &RenderBuffers, settings: &RenderSettings, options: &Options) {
+    match buffers.save(&options.output, options.format, &settings.display) {
+        Ok(paths) => {
+            for path in paths {
+                println!("saved {}", path.display());
+            }
+        },
+        Err(error) => {
+            eprintln!("could not write {}: {}", options.output.display(), error);
+            process::exit(1);
         }
     }
-
-    sample_pos
 }
 
-fn write_to_file(buffer: & UnsafeRgbaImage) {
-    buffer.as_ref().save("image.png").unwrap();
+fn save_checkpoint(snapshot: &Snapshot, path: &Path) {
+    match snapshot.checkpoint().save(path) {
+        Ok(()) => println!("saved checkpoint {}", path.display()),
+        Err(error) => eprintln!("warning: could not write checkpoint {}: {}", path.display(), error)
+    }
 }
@@ -4,9 +4,9 @@ use std::f32::consts;
 pub fn clamp<T>(value: T, min: T, max: T) -> T 
     where T : PartialOrd {
         if value < min {
-            return min;
+            min
         } else if value > max {
-            return max;
+            max
         } else {
             value
         }
@@ -15,4 +15,4 @@ pub fn clamp<T>(value: T, min: T, max: T) -> T
 #[inline]
 pub fn degree_to_radians(deg: f32) -> f32 {
     deg * consts::PI / 180.0 
-}
\ No newline at end of file
+}
@@ -177,6 +177,12 @@ impl Matrix {
         }
     }
 
+    /// Transforms a direction, unlike `Vector * Matrix` the translation is left out.
+    #[inline]
+    pub fn transform_direction(&self, v: Vector) -> Vector {
+        self.row_1 * v.x() + self.row_2 * v.y() + self.row_3 * v.z()
+    }
+
     #[inline]
     pub fn transpose(&self) -> Self {
         let (m00, m01, m02, m03) = self.row_1.into();
@@ -232,6 +238,16 @@ impl Matrix {
         }
     }
 
+    #[inline]
+    pub fn roatation_z(rotation: f32) -> Self {
+        Self {
+            row_1: Vector::vec4(rotation.cos(), rotation.sin(), 0.0, 0.0),
+            row_2: Vector::vec4(-rotation.sin(), rotation.cos(), 0.0, 0.0),
+            row_3: Vector::vec4(0.0, 0.0, 1.0, 0.0),
+            row_4: Vector::vec4(0.0, 0.0, 0.0, 1.0)
+        }
+    }
+
     #[inline]
     pub fn look_at_rh(eye_pos: Vector, focus: Vector, up: Vector) -> Self {
         let temp = up;
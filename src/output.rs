@@ -0,0 +1,251 @@
+use crate::framebuffer::{Framebuffer, RenderBuffers};
+use crate::tonemap::DisplayTransform;
+
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, Vec2, WritableImage, f16};
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+/// Precision of the exr channels, integer layers like ids are always stored as float.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPrecision {
+    Half,
+    Float
+}
+
+/// Image formats the framebuffer can be written to. Png, jpeg, bmp and tiff are 8 bit and
+/// go through the display transform, exr and hdr keep the linear radiance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Tiff,
+    Exr(ExrPrecision),
+    Hdr
+}
+
+impl OutputFormat {
+    /// Looks up a format by name or file extension, exr defaults to half precision.
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "tif" | "tiff" => Some(OutputFormat::Tiff),
+            "exr" => Some(OutputFormat::Exr(ExrPrecision::Half)),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(io::Error),
+    Exr(exr::error::Error),
+    /// An exr layer doesn't have the size of the first one, all layers of an exr share one
+    /// resolution.
+    LayerSize { layer: String, width: u32, height: u32, expected_width: u32, expected_height: u32 }
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputError::Io(error) => write!(f, "{}", error),
+            OutputError::Exr(error) => write!(f, "{}", error),
+            OutputError::LayerSize { layer, width, height, expected_width, expected_height } => {
+                write!(f, "exr layer '{}' is {}x{} but the image is {}x{}", layer, width, height, expected_width, expected_height)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(error: io::Error) -> Self {
+        OutputError::Io(error)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(error: exr::error::Error) -> Self {
+        OutputError::Exr(error)
+    }
+}
+
+pub fn save<P: AsRef<Path>>(framebuffer: &Framebuffer, path: P, format: OutputFormat, display: &DisplayTransform) -> Result<(), OutputError> {
+    let image_format = match format {
+        OutputFormat::Exr(precision) => return write_exr(path, &[ExrLayer::radiance("", framebuffer)], precision),
+        OutputFormat::Hdr => return write_hdr(path, framebuffer),
+        _ => image_format(format)
+    };
+
+    framebuffer.to_rgb_image(display).save_with_format(path, image_format)?;
+    Ok(())
+}
+
+/// Saves the beauty pass and its aovs, see [`RenderBuffers::save`].
+pub fn save_buffers(buffers: &RenderBuffers, path: &Path, format: OutputFormat, display: &DisplayTransform) -> Result<Vec<PathBuf>, OutputError> {
+    let light_names: Vec<String> = (0..buffers.lights.len()).map(|index| format!("light_{}", index)).collect();
+
+    if let OutputFormat::Exr(precision) = format {
+        let mut layers = vec![ExrLayer::radiance("", &buffers.beauty)];
+        for (aov, framebuffer) in buffers.aovs.iter() {
+            layers.push(if aov.is_radiance() {
+                ExrLayer::radiance(aov.name(), framebuffer)
+            } else if aov.is_integer() {
+                ExrLayer::integer(aov.name(), framebuffer, aov.exr_channels())
+            } else {
+                ExrLayer::data(aov.name(), framebuffer, aov.exr_channels())
+            });
+        }
+        layers.extend(light_names.iter().zip(buffers.lights.iter()).map(|(name, framebuffer)| ExrLayer::radiance(name, framebuffer)));
+        write_exr(path, &layers, precision)?;
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    save(&buffers.beauty, path, format, display)?;
+    let mut paths = vec![path.to_path_buf()];
+
+    for (aov, framebuffer) in buffers.aovs.iter() {
+        let aov_path = pass_path(path, aov.name());
+        match format {
+            OutputFormat::Hdr => write_hdr(&aov_path, framebuffer)?,
+            _ => aov.to_rgb_image(framebuffer, display).save_with_format(&aov_path, image_format(format))?
+        }
+        paths.push(aov_path);
+    }
+
+    for (name, framebuffer) in light_names.iter().zip(buffers.lights.iter()) {
+        let light_path = pass_path(path, name);
+        save(framebuffer, &light_path, format, display)?;
+        paths.push(light_path);
+    }
+
+    Ok(paths)
+}
+
+/// Path a pass is written to when the format has no layers, `image.png` becomes
+/// `image.depth.png`.
+pub fn pass_path(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, name, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, name)
+    };
+    path.with_file_name(file_name)
+}
+
+fn image_format(format: OutputFormat) -> image::ImageFormat {
+    match format {
+        OutputFormat::Jpeg => image::ImageFormat::JPEG,
+        OutputFormat::Bmp => image::ImageFormat::BMP,
+        OutputFormat::Tiff => image::ImageFormat::TIFF,
+        _ => image::ImageFormat::PNG
+    }
+}
+
+/// A framebuffer stored as a layer of an exr. Radiance layers drop negative values, data
+/// layers like normals and positions are stored unchanged.
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub framebuffer: &'a Framebuffer,
+    pub channels: &'a [&'a str],
+    radiance: bool,
+    integer: bool
+}
+
+impl<'a> ExrLayer<'a> {
+    pub fn radiance(name: &'a str, framebuffer: &'a Framebuffer) -> Self {
+        Self {
+            name: name,
+            framebuffer: framebuffer,
+            channels: &["R", "G", "B"],
+            radiance: true,
+            integer: false
+        }
+    }
+
+    /// The channels are taken from x, y and z of the framebuffer in order.
+    pub fn data(name: &'a str, framebuffer: &'a Framebuffer, channels: &'a [&'a str]) -> Self {
+        Self {
+            name: name,
+            framebuffer: framebuffer,
+            channels: channels,
+            radiance: false,
+            integer: false
+        }
+    }
+
+    /// A data layer holding whole numbers like ids, stored as float even in half precision
+    /// exrs since half loses integers above 2048.
+    pub fn integer(name: &'a str, framebuffer: &'a Framebuffer, channels: &'a [&'a str]) -> Self {
+        Self {
+            integer: true,
+            ..Self::data(name, framebuffer, channels)
+        }
+    }
+}
+
+/// Writes the layers into a single part exr. A layer named "" gets plain channel names like
+/// `R`, the others are prefixed with the layer name like `normal.X`. All framebuffers must
+/// have the same size, otherwise nothing is written and `OutputError::LayerSize` returned.
+pub fn write_exr<P: AsRef<Path>>(path: P, layers: &[ExrLayer], precision: ExrPrecision) -> Result<(), OutputError> {
+    let (width, height) = match layers.first() {
+        Some(layer) => (layer.framebuffer.width as usize, layer.framebuffer.height as usize),
+        None => return Err(OutputError::Io(io::Error::new(io::ErrorKind::InvalidInput, "no layers to write")))
+    };
+
+    if let Some(layer) = layers.iter().find(|layer| layer.framebuffer.width as usize != width || layer.framebuffer.height as usize != height) {
+        return Err(OutputError::LayerSize {
+            layer: layer.name.to_string(),
+            width: layer.framebuffer.width,
+            height: layer.framebuffer.height,
+            expected_width: width as u32,
+            expected_height: height as u32
+        });
+    }
+
+    let mut channels = SmallVec::new();
+    for layer in layers {
+
+        for (component, suffix) in layer.channels.iter().enumerate() {
+            let channel_name = if layer.name.is_empty() {suffix.to_string()} else {format!("{}.{}", layer.name, suffix)};
+            let values = layer.framebuffer.pixels().iter()
+                .map(|pixel| if layer.radiance {component_of(*pixel, component)} else {<[f32; 4]>::from(*pixel)[component]});
+            let samples = match precision {
+                ExrPrecision::Half if !layer.integer => FlatSamples::F16(values.map(f16::from_f32).collect()),
+                _ => FlatSamples::F32(values.collect())
+            };
+            channels.push(AnyChannel::new(channel_name.as_str(), samples));
+        }
+    }
+
+    let layer = Layer::new(Vec2(width, height), LayerAttributes::default(), Encoding::FAST_LOSSLESS, AnyChannels::sort(channels));
+    Image::from_layer(layer).write().to_file(path)?;
+    Ok(())
+}
+
+/// Writes a Radiance rgbe .hdr file.
+pub fn write_hdr<P: AsRef<Path>>(path: P, framebuffer: &Framebuffer) -> Result<(), OutputError> {
+    let writer = BufWriter::new(File::create(path)?);
+    let pixels: Vec<image::Rgb<f32>> = framebuffer.pixels().iter()
+        .map(|pixel| image::Rgb([component_of(*pixel, 0), component_of(*pixel, 1), component_of(*pixel, 2)]))
+        .collect();
+
+    image::hdr::HDREncoder::new(writer).encode(&pixels, framebuffer.width as usize, framebuffer.height as usize)?;
+    Ok(())
+}
+
+// negative or nan radiance can't be stored in rgbe and breaks compositing
+fn component_of(pixel: crate::Vector, component: usize) -> f32 {
+    let value = match component {
+        0 => pixel.x(),
+        1 => pixel.y(),
+        _ => pixel.z()
+    };
+
+    if value > 0.0 {value} else {0.0}
+}
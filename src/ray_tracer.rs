@@ -1,44 +1,105 @@
 use crate::vector_simd::Vector;
-use crate::geometry::{Mesh};
 use crate::scene::*;
-use crate::shading::{calculate_color, ShadingData};
+use crate::shading::{calculate_color, calculate_lighting, LightingComponents, ShadingData, materials::Material};
 use crate::Stats;
 use crate::RenderSettings;
-use crate::bvh::LinearBVHNode;
+use crate::bvh::MAX_BVH_DEPTH;
 use std::f32;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum RayType {
     CameraRay,
-    ShadowRay,
     SpecularRay,
     DiffuseRay
 } 
 
 pub fn cast_ray(origin: Vector, direction: Vector, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, stats: & mut Stats) -> Vector {
-    match trace(origin, direction, &scene.scene_objects, &scene.bvh, &scene.object_indices, f32::INFINITY, current_ray_depth, settings, ray_type, stats) {
+    match trace(origin, direction, scene, f32::INFINITY, current_ray_depth, settings, stats) {
         None => settings.background_color,
         Some(i) => {
-            let mesh = &scene.scene_objects[i.mesh_index].mesh;
-            
-            let ind_1 = mesh.indices[i.triangle_index] as usize;
-            let ind_2 = mesh.indices[i.triangle_index + 1] as usize;
-            let ind_3 = mesh.indices[i.triangle_index + 2] as usize;
+            let hit = surface_hit(origin, direction, &i, scene);
+            let data = ShadingData::new(hit.position, hit.normal, hit.texture_coord, hit.material);
 
-            let v_0 = &mesh.vertices[ind_1];
-            let v_1 = &mesh.vertices[ind_2];
-            let v_2 = &mesh.vertices[ind_3];
+            calculate_color(data, direction, scene, current_ray_depth, settings, ray_type, stats)
+        }
+    }
+}
 
-            let position = origin + direction * i.t;
-            
-            let normal = (v_0.normal.vec3_normalize() * (1.0 - i.u - i.v) + v_1.normal.vec3_normalize() * i.u + v_2.normal.vec3_normalize() * i.v).vec3_normalize();
-            let data = ShadingData::new(position, normal, Vector::vec2(0.0, 0.0), scene.scene_objects[i.mesh_index].material);
+/// What a camera ray saw, the hit and lighting are `None` when it missed everything.
+pub struct CameraSample {
+    pub color: Vector,
+    pub hit: Option<SurfaceHit>,
+    pub lighting: Option<LightingComponents>
+}
 
-            calculate_color(data, direction, scene, current_ray_depth, settings, ray_type, stats)
+/// Casts a camera ray and also returns the surface it hit and the lighting components,
+/// used for the aovs. The direct light of every light is added to `per_light` when given.
+pub fn cast_camera_ray(origin: Vector, direction: Vector, scene: &SceneData, settings: RenderSettings, per_light: Option<&mut [Vector]>, stats: & mut Stats) -> CameraSample {
+    let tests_before = stats.num_tringle_tests;
+    let result = trace(origin, direction, scene, f32::INFINITY, 0, settings, stats);
+    stats.num_camera_triangle_tests += stats.num_tringle_tests - tests_before;
+
+    match result {
+        None => CameraSample { color: settings.background_color, hit: None, lighting: None },
+        Some(i) => {
+            let hit = surface_hit(origin, direction, &i, scene);
+            let data = ShadingData::new(hit.position, hit.normal, hit.texture_coord, hit.material);
+            let lighting = calculate_lighting(data, direction, scene, 0, settings, RayType::CameraRay, per_light, stats);
+
+            CameraSample { color: lighting.total(), hit: Some(hit), lighting: Some(lighting) }
         }
     }
 }
 
+/// The interpolated surface at a ray hit.
+pub struct SurfaceHit {
+    pub distance: f32,
+    pub position: Vector,
+    pub normal: Vector,
+    pub texture_coord: Vector,
+    pub material: Material,
+    pub object_index: usize
+}
+
+fn surface_hit(origin: Vector, direction: Vector, i: &TraceResult, scene: &SceneData) -> SurfaceHit {
+    let scene_object = &scene.scene_objects[i.mesh_index];
+    let mesh = scene_object.mesh();
+
+    let ind_1 = mesh.indices[i.triangle_index] as usize;
+    let ind_2 = mesh.indices[i.triangle_index + 1] as usize;
+    let ind_3 = mesh.indices[i.triangle_index + 2] as usize;
+
+    let v_0 = &mesh.vertices[ind_1];
+    let v_1 = &mesh.vertices[ind_2];
+    let v_2 = &mesh.vertices[ind_3];
+
+    let position = origin + direction * i.t;
+
+    // vertex normals of instances are moved to world space before they are interpolated,
+    // like the normals of meshes with their transform baked in
+    let (n_0, n_1, n_2) = match scene_object.transform {
+        Some(ref transform) => (transform.normal_matrix.transform_direction(v_0.normal), transform.normal_matrix.transform_direction(v_1.normal), transform.normal_matrix.transform_direction(v_2.normal)),
+        None => (v_0.normal, v_1.normal, v_2.normal)
+    };
+    let normal = (n_0.vec3_normalize() * (1.0 - i.u - i.v) + n_1.vec3_normalize() * i.u + n_2.vec3_normalize() * i.v).vec3_normalize();
+    let texture_coord = v_0.texture_coord * (1.0 - i.u - i.v) + v_1.texture_coord * i.u + v_2.texture_coord * i.v;
+    let mut material = scene_object.material;
+
+    if !mesh.colors.is_empty() {
+        let color = mesh.colors[ind_1] * (1.0 - i.u - i.v) + mesh.colors[ind_2] * i.u + mesh.colors[ind_3] * i.v;
+        material.albedo *= color;
+    }
+
+    SurfaceHit {
+        distance: i.t,
+        position: position,
+        normal: normal,
+        texture_coord: texture_coord,
+        material: material,
+        object_index: i.mesh_index
+    }
+}
+
 pub struct TraceResult {
     u: f32,
     v: f32,
@@ -47,52 +108,124 @@ pub struct TraceResult {
     t: f32
 }
 
-pub fn trace(origin: Vector, direction: Vector, scene_objects: &[SceneObject], nodes: &[LinearBVHNode], indices: &[usize], near: f32, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, stats: & mut Stats) -> Option<TraceResult> {
-    let mut found:Option<TraceResult> = None;
-    
+pub fn trace(origin: Vector, direction: Vector, scene: &SceneData, near: f32, current_ray_depth: u32, settings: RenderSettings, stats: & mut Stats) -> Option<TraceResult> {
     if current_ray_depth > settings.max_ray_depth {
-        return found;
+        return None;
     }
     
     stats.num_rays_shot += 1;
-    let mut closest = near;
+    traverse(&BvhRay::new(origin, direction), scene, 0.0, near, false, stats)
+}
 
-    let inv_dir = 1.0 / direction;
-    let sign_x = if inv_dir.x() < 0.0 {1} else {0};
-    let sign_y = if inv_dir.y() < 0.0 {1} else {0};
-    let sign_z = if inv_dir.z() < 0.0 {1} else {0};
-    let sign = [sign_x, sign_y, sign_z];
+/// Whether anything blocks the ray between `t_min` and `t_max`, for shadow rays. The search
+/// ends at the first hit found instead of looking for the closest one. Like `trace`, rays
+/// past the maximum ray depth aren't traced and are never occluded.
+#[allow(clippy::too_many_arguments)]
+pub fn occluded(origin: Vector, direction: Vector, scene: &SceneData, t_min: f32, t_max: f32, current_ray_depth: u32, settings: RenderSettings, stats: & mut Stats) -> bool {
+    if current_ray_depth > settings.max_ray_depth {
+        return false;
+    }
+
+    stats.num_rays_shot += 1;
+    stats.num_shadow_rays += 1;
+    let tests_before = stats.num_tringle_tests;
+
+    let ray = BvhRay::new(origin, direction);
+    let hit = traverse(&ray, scene, t_min, t_max, true, stats).is_some();
+
+    let any_hit_tests = stats.num_tringle_tests - tests_before;
+    stats.num_shadow_triangle_tests += any_hit_tests;
+    if hit {
+        stats.num_occluded_shadow_rays += 1;
+    }
+
+    // some shadow rays are traced again for the closest hit to measure what stopping at the
+    // first hit saves, those tests don't count as tests of the render
+    if stats.num_shadow_rays % SHADOW_RAY_COMPARISON_INTERVAL == 0 {
+        let tests_before = stats.num_tringle_tests;
+        traverse(&ray, scene, t_min, t_max, false, stats);
+        stats.num_compared_shadow_rays += 1;
+        stats.num_compared_any_hit_tests += any_hit_tests;
+        stats.num_compared_closest_hit_tests += stats.num_tringle_tests - tests_before;
+        stats.num_tringle_tests = tests_before;
+    }
+    hit
+}
+
+// one in this many shadow rays is also traced for the closest hit
+const SHADOW_RAY_COMPARISON_INTERVAL: u128 = 64;
+
+// a ray with the inverse direction and signs the bounding box tests use
+struct BvhRay {
+    origin: Vector,
+    direction: Vector,
+    inv_dir: Vector,
+    sign: [i8; 3]
+}
+
+impl BvhRay {
+    fn new(origin: Vector, direction: Vector) -> Self {
+        let inv_dir = 1.0 / direction;
+        Self {
+            origin: origin,
+            direction: direction,
+            inv_dir: inv_dir,
+            sign: ray_sign(inv_dir)
+        }
+    }
+}
+
+// walks the object bvh for hits in [t_min, t_max), the closest one or with `any_hit` the
+// first one found
+fn traverse(ray: &BvhRay, scene: &SceneData, t_min: f32, t_max: f32, any_hit: bool, stats: & mut Stats) -> Option<TraceResult> {
+    let nodes = &scene.bvh;
+    let mut found:Option<TraceResult> = None;
+    let mut closest = t_max;
 
     let mut to_visit_offset = 0;
     let mut current_node_index = 0;
-    let mut nodes_to_visit = [0; 64];
+    let mut nodes_to_visit = [0; MAX_BVH_DEPTH];
 
     loop {
         let node = nodes[current_node_index];
 
-        if node.bounding_box.intersect(origin, inv_dir, sign) {
+        if node.bounding_box.intersect(ray.origin, ray.inv_dir, ray.sign) {
             //leaf nodes
             if node.num_prim > 0 {
                 for i in 0..node.num_prim {
                     let index = (node.prim_offset + i as i32) as usize;
-                    let mesh_index = indices[index];
-                    match intersect_mesh(origin, direction, &scene_objects[mesh_index].mesh, stats) {
-                        Some(mesh_result) => {
-                            if mesh_result.t < closest {
-                                closest = mesh_result.t;
-
-                                let result = TraceResult {
-                                    u: mesh_result.u,
-                                    v: mesh_result.v,
-                                    triangle_index: mesh_result.triangle_index,
-                                    mesh_index: mesh_index,
-                                    t: mesh_result.t
-                                };
-                                found = Some(result);
-                            }
+                    let mesh_index = scene.object_indices[index];
+                    let scene_object = &scene.scene_objects[mesh_index];
+
+                    // instances are intersected in object space, where their triangles keep
+                    // the winding they were made with. The direction isn't normalized after
+                    // the transform so t stays the same in both spaces
+                    let mesh_result = match scene_object.transform {
+                        Some(ref transform) => {
+                            let object_ray = BvhRay::new(ray.origin * transform.inverse, transform.inverse.transform_direction(ray.direction));
+                            intersect_mesh(&object_ray, &scene_object.resource, t_min, closest, any_hit, stats)
                         },
-                        None => ()
-                    }                 
+                        None => intersect_mesh(ray, &scene_object.resource, t_min, closest, any_hit, stats)
+                    };
+
+                    if let Some(mesh_result) = mesh_result {
+                        if mesh_result.t < closest {
+                            closest = mesh_result.t;
+
+                            let result = TraceResult {
+                                u: mesh_result.u,
+                                v: mesh_result.v,
+                                triangle_index: mesh_result.triangle_index,
+                                mesh_index: mesh_index,
+                                t: mesh_result.t
+                            };
+                            found = Some(result);
+
+                            if any_hit {
+                                return found;
+                            }
+                        }
+                    }
                 }
 
                 if to_visit_offset == 0 {
@@ -103,12 +236,12 @@ pub fn trace(origin: Vector, direction: Vector, scene_objects: &[SceneObject], n
                 current_node_index = nodes_to_visit[to_visit_offset];
 
             } else {
-                if sign[node.axis as usize] == 1 {
+                if ray.sign[node.axis as usize] == 1 {
                     nodes_to_visit[to_visit_offset] = current_node_index + 1;
                     current_node_index = node.second_child_offset as usize;
                 } else {
                     nodes_to_visit[to_visit_offset] = node.second_child_offset as usize;
-                    current_node_index = current_node_index + 1;
+                    current_node_index += 1;
                 }
 
                 to_visit_offset += 1;
@@ -155,39 +288,81 @@ struct MeshIntersectResult {
     t: f32
 }
 
-fn intersect_mesh(origin: Vector, direction: Vector, scene_object: &Mesh, stats: & mut Stats) -> Option<MeshIntersectResult> {
-        let mut found:Option<MeshIntersectResult> = None;
-        let mut closest = f32::INFINITY;
-
-       let mut index = 0;
-       for _ in 0..scene_object.num_tris {
-            let v_0 = scene_object.indices[index] as usize;
-            let v_1 = scene_object.indices[index + 1] as usize;
-            let v_2 = scene_object.indices[index + 2] as usize;
-
-            // let v = scene_object.indices[index..(index + )]
-
-            match intersect_triangle(origin, direction, scene_object.vertices[v_0].pos, scene_object.vertices[v_1].pos, scene_object.vertices[v_2].pos, stats) {
-                Some(tri_result) => {
-                    if tri_result.t < closest {
-                        closest = tri_result.t;
-
-                        let result = MeshIntersectResult {
-                            u: tri_result.u,
-                            v: tri_result.v,
-                            triangle_index: index,
-                            t: closest
-                        };
-                        found = Some(result);
+fn ray_sign(inv_dir: Vector) -> [i8; 3] {
+    let sign_x = if inv_dir.x() < 0.0 {1} else {0};
+    let sign_y = if inv_dir.y() < 0.0 {1} else {0};
+    let sign_z = if inv_dir.z() < 0.0 {1} else {0};
+    [sign_x, sign_y, sign_z]
+}
+
+// walks the triangle bvh of the mesh, only hits from `t_min` up to `closest` are returned
+fn intersect_mesh(ray: &BvhRay, resource: &MeshResource, t_min: f32, closest: f32, any_hit: bool, stats: & mut Stats) -> Option<MeshIntersectResult> {
+    let mesh = &resource.mesh;
+    let bvh = resource.bvh();
+    let nodes = &bvh.nodes;
+    let mut found: Option<MeshIntersectResult> = None;
+    let mut closest = closest;
+
+    let mut to_visit_offset = 0;
+    let mut current_node_index = 0;
+    let mut nodes_to_visit = [0; MAX_BVH_DEPTH];
+
+    loop {
+        let node = &nodes[current_node_index];
+
+        if node.bounding_box.intersect(ray.origin, ray.inv_dir, ray.sign) {
+            if node.num_prim > 0 {
+                for i in 0..node.num_prim {
+                    let index = bvh.triangles[node.prim_offset as usize + i as usize] as usize * 3;
+                    let v_0 = mesh.indices[index] as usize;
+                    let v_1 = mesh.indices[index + 1] as usize;
+                    let v_2 = mesh.indices[index + 2] as usize;
+
+                    if let Some(tri_result) = intersect_triangle(ray.origin, ray.direction, mesh.vertices[v_0].pos, mesh.vertices[v_1].pos, mesh.vertices[v_2].pos, stats) {
+                        if tri_result.t >= t_min && tri_result.t < closest {
+                            closest = tri_result.t;
+                            found = Some(MeshIntersectResult {
+                                u: tri_result.u,
+                                v: tri_result.v,
+                                triangle_index: index,
+                                t: closest
+                            });
+
+                            if any_hit {
+                                return found;
+                            }
+                        }
                     }
-                },
-                None => ()
+                }
+
+                if to_visit_offset == 0 {
+                    break;
+                }
+
+                to_visit_offset -= 1;
+                current_node_index = nodes_to_visit[to_visit_offset];
+            } else {
+                if ray.sign[node.axis as usize] == 1 {
+                    nodes_to_visit[to_visit_offset] = current_node_index + 1;
+                    current_node_index = node.second_child_offset as usize;
+                } else {
+                    nodes_to_visit[to_visit_offset] = node.second_child_offset as usize;
+                    current_node_index += 1;
+                }
+
+                to_visit_offset += 1;
+            }
+        } else {
+            if to_visit_offset == 0 {
+                break;
             }
 
-            index += 3;
+            to_visit_offset -= 1;
+            current_node_index = nodes_to_visit[to_visit_offset];
         }
+    }
 
-    found    
+    found
 }
 
 struct TriangleIntersectResult {
@@ -205,7 +380,10 @@ fn intersect_triangle(ray_origin: Vector, ray_dir: Vector, v_0: Vector, v_1: Vec
     let p = ray_dir.vec3_cross(v0v2);
     let det = v0v1.vec3_dot_f32(p);
 
-    if det < f32::EPSILON {
+    // back faces and rays parallel to the triangle miss. Only the sign is tested, the size of
+    // det scales with the transform of an instance and with the length of its object space
+    // direction
+    if det <= 0.0 {
         return None;
     }
 
@@ -214,7 +392,7 @@ fn intersect_triangle(ray_origin: Vector, ray_dir: Vector, v_0: Vector, v_1: Vec
     let t_vec = ray_origin - v_0;
     let u = t_vec.vec3_dot_f32(p) * inv_det;
 
-    if u < 0.0 || u > 1.0 {
+    if !(0.0..=1.0).contains(&u) {
         return None;
     }
 
@@ -0,0 +1,382 @@
+use crate::adaptive::PixelVariance;
+use crate::aov::{self, Aov};
+use crate::checkpoint::{Checkpoint, CheckpointError};
+use crate::film::Film;
+use crate::framebuffer::RenderBuffers;
+use crate::sampler::{self, Sampler, sample_2d};
+use crate::ray_tracer::{CameraSample, cast_camera_ray};
+use crate::scene::SceneData;
+use crate::vector_simd::Vector;
+use crate::{RenderSettings, Stats};
+
+use std::cell::UnsafeCell;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+struct UnsafeBuffers(UnsafeCell<RenderBuffers>);
+
+impl UnsafeBuffers {
+    fn new(buffers: RenderBuffers) -> Self {
+        Self(UnsafeCell::new(buffers))
+    }
+
+    // every pixel is rendered by exactly one thread at a time so writes never overlap
+    fn put_aov(&self, x: u32, y: u32, aov: Aov, value: Vector) {
+        let buffers = unsafe { self.0.get().as_mut() }.unwrap();
+        if let Some((_, framebuffer)) = buffers.aovs.iter_mut().find(|(buffer_aov, _)| *buffer_aov == aov) {
+            framebuffer.put_pixel(x, y, value);
+        }
+    }
+
+    // only called while no render threads are running
+    fn get(&self) -> &RenderBuffers {
+        unsafe { self.0.get().as_ref() }.unwrap()
+    }
+}
+
+unsafe impl Sync for UnsafeBuffers {}
+
+/// When a progressive render hands out snapshots, after a number of passes, after some
+/// time or whichever comes first when both are set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SnapshotInterval {
+    pub passes: Option<u32>,
+    pub seconds: Option<f32>
+}
+
+/// Stops a running render from another thread. The render threads finish the pixel they
+/// are working on and the render returns the image as far as it got.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Renders scenes on a pool of worker threads that pull pixels off a shared counter, one
+/// sample per pixel and pass.
+pub struct Renderer {
+    pub settings: RenderSettings,
+    pub threads: usize,
+    /// Cancels the renders of this renderer, clone it to cancel from another thread. A
+    /// cancelled token stays cancelled, a render started with it stops right away, so set a
+    /// new token before rendering again.
+    pub cancel: CancellationToken
+}
+
+impl Renderer {
+    /// Creates a renderer using one thread per logical cpu.
+    pub fn new(settings: RenderSettings) -> Self {
+        Self::with_threads(settings, num_cpus::get())
+    }
+
+    pub fn with_threads(settings: RenderSettings, threads: usize) -> Self {
+        Self {
+            settings: settings,
+            threads: threads.max(1),
+            cancel: CancellationToken::new()
+        }
+    }
+
+    /// Renders the scene as seen from its camera and returns the image and the aovs selected
+    /// in the settings together with the statistics of all render threads. The pixels take
+    /// their samples in passes like `render_progressive`, so when cancelled or out of time
+    /// the image has the passes finished so far.
+    pub fn render(&self, scene: &SceneData) -> (RenderBuffers, Stats) {
+        let frame = Frame::new(self.settings, scene, self.cancel.clone());
+        let pixels = (0..self.settings.width * self.settings.height).map(|_| Mutex::new(PixelState::new())).collect();
+        self.run_passes(frame, pixels, 0, SnapshotInterval::default(), |_| {})
+    }
+
+    /// Renders the whole frame in passes of one sample per pixel, handing a snapshot of the
+    /// render to `on_snapshot` whenever the interval has passed and once more after the last
+    /// pass. Gives the same image as `render` once all passes are done, when cancelled or out
+    /// of time the image has the passes finished so far.
+    pub fn render_progressive<F>(&self, scene: &SceneData, interval: SnapshotInterval, on_snapshot: F) -> (RenderBuffers, Stats)
+        where F: FnMut(&Snapshot)
+    {
+        let frame = Frame::new(self.settings, scene, self.cancel.clone());
+        let pixels = (0..self.settings.width * self.settings.height).map(|_| Mutex::new(PixelState::new())).collect();
+        self.run_passes(frame, pixels, 0, interval, on_snapshot)
+    }
+
+    /// Continues a progressive render from a checkpoint. The settings of the renderer may
+    /// only differ from the checkpointed ones in the camera sample counts, the time limit
+    /// and the display transform, so raising the sample counts adds samples to the same
+    /// image.
+    pub fn resume_progressive<F>(&self, scene: &SceneData, checkpoint: &Checkpoint, interval: SnapshotInterval, on_snapshot: F) -> Result<(RenderBuffers, Stats), CheckpointError>
+        where F: FnMut(&Snapshot)
+    {
+        checkpoint.check_scene(scene)?;
+        checkpoint.check_settings(&self.settings)?;
+        let mut frame = Frame::new(self.settings, scene, self.cancel.clone());
+        if frame.film.layers() != checkpoint.film_layers {
+            return Err(CheckpointError::Mismatch("the number of lights differs".to_string()));
+        }
+
+        frame.film.set_data(&checkpoint.film);
+        for (aov, framebuffer) in frame.buffers.0.get_mut().aovs.iter_mut() {
+            if let Some((_, stored)) = checkpoint.aovs.iter().find(|(stored_aov, _)| stored_aov == aov) {
+                *framebuffer = stored.clone();
+            }
+        }
+        let pixels = checkpoint.pixels.iter().map(|pixel| Mutex::new(*pixel)).collect();
+        Ok(self.run_passes(frame, pixels, checkpoint.passes, interval, on_snapshot))
+    }
+
+    fn run_passes<F>(&self, mut frame: Frame, pixels: Vec<Mutex<PixelState>>, first_pass: u32, interval: SnapshotInterval, mut on_snapshot: F) -> (RenderBuffers, Stats)
+        where F: FnMut(&Snapshot)
+    {
+        let num_render_jobs = pixels.len();
+        let now = Instant::now();
+        let mut last_snapshot = Instant::now();
+        let mut stats = Stats::default();
+        let mut pass = first_pass;
+        // set when a pixel still needed samples as the render was cancelled or ran out of time
+        let stopped = AtomicBool::new(false);
+
+        loop {
+            let render_job_counter = AtomicUsize::new(0);
+            let sampled = AtomicBool::new(false);
+
+            stats += frame.run_threads(self.threads, |sample_values, stats| {
+                loop {
+                    let i = render_job_counter.fetch_add(1, Ordering::Relaxed);
+
+                    if i >= num_render_jobs || stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    let mut pixel = pixels[i].lock().unwrap();
+                    if pixel.needs_sample(&frame.settings) {
+                        if frame.should_stop() {
+                            stopped.store(true, Ordering::Relaxed);
+                            break;
+                        }
+
+                        let x = i as u32 % frame.settings.width;
+                        let y = i as u32 / frame.settings.width;
+                        sampler::start_pixel(x, y);
+                        frame.render_sample(x, y, &mut pixel, sample_values, stats);
+                        sampled.store(true, Ordering::Relaxed);
+                    }
+                }
+            });
+            // samples taken before stopping are kept, the pixels already counted them
+            frame.film.end_pass(self.threads);
+
+            if !sampled.into_inner() || stopped.load(Ordering::Relaxed) {
+                break;
+            }
+            pass += 1;
+
+            let passes_due = interval.passes.is_some_and(|passes| pass % passes.max(1) == 0);
+            let time_due = interval.seconds.is_some_and(|seconds| last_snapshot.elapsed().as_secs_f32() >= seconds);
+            if passes_due || time_due {
+                on_snapshot(&Snapshot { passes: pass, last: false, frame: &frame, pixels: &pixels });
+                last_snapshot = Instant::now();
+            }
+        }
+        stats.render_time = now.elapsed().as_secs() as f64 + now.elapsed().subsec_nanos() as f64 * 1e-9;
+        stats.stopped_early = stopped.into_inner();
+
+        on_snapshot(&Snapshot { passes: pass, last: true, frame: &frame, pixels: &pixels });
+        (frame.resolve(), stats)
+    }
+}
+
+/// A progressive render between two passes.
+pub struct Snapshot<'a> {
+    /// Passes finished so far.
+    pub passes: u32,
+    /// Set for the snapshot taken after the render finished or stopped.
+    pub last: bool,
+    frame: &'a Frame<'a>,
+    pixels: &'a [Mutex<PixelState>]
+}
+
+impl<'a> Snapshot<'a> {
+    /// The image and aovs so far.
+    pub fn buffers(&self) -> RenderBuffers {
+        self.frame.resolve()
+    }
+
+    /// Everything needed to continue the render later with `Renderer::resume_progressive`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        let aovs = self.frame.buffers.get().aovs.iter().filter(|(aov, _)| !aov.is_filtered()).cloned().collect();
+
+        Checkpoint {
+            settings: self.frame.settings,
+            scene_fingerprint: self.frame.scene.fingerprint,
+            passes: self.passes,
+            film_layers: self.frame.film.layers(),
+            film: self.frame.film.data(),
+            pixels: self.pixels.iter().map(|pixel| *pixel.lock().unwrap()).collect(),
+            aovs: aovs
+        }
+    }
+}
+
+// what is known about a pixel between its samples
+#[derive(Clone, Copy)]
+pub(crate) struct PixelState {
+    pub(crate) variance: PixelVariance,
+    pub(crate) sample_count: u32,
+    pub(crate) closest_to_center: f32
+}
+
+impl PixelState {
+    fn new() -> Self {
+        Self {
+            variance: PixelVariance::default(),
+            sample_count: 0,
+            closest_to_center: f32::INFINITY
+        }
+    }
+
+    fn needs_sample(&self, settings: &RenderSettings) -> bool {
+        let min_samples = settings.aa_samples * settings.aa_samples;
+        match settings.adaptive {
+            _ if self.sample_count < min_samples => true,
+            Some(adaptive) => self.sample_count < adaptive.max_samples && self.variance.error() >= adaptive.threshold,
+            None => false
+        }
+    }
+}
+
+// everything the render threads share while rendering one image
+struct Frame<'a> {
+    settings: RenderSettings,
+    scene: &'a SceneData,
+    aspect_ratio: f32,
+    material_ids: Vec<u32>,
+    // the beauty pass, the filtered aovs and the light passes are filtered through the
+    // pixel filter, one film layer each
+    filtered_aovs: Vec<Aov>,
+    film: Film,
+    buffers: UnsafeBuffers,
+    cancel: CancellationToken,
+    deadline: Option<Instant>
+}
+
+impl<'a> Frame<'a> {
+    fn new(settings: RenderSettings, scene: &'a SceneData, cancel: CancellationToken) -> Self {
+        let filtered_aovs: Vec<Aov> = settings.aovs.iter().filter(|aov| aov.is_filtered()).collect();
+        let light_count = if settings.aovs.light_passes() {scene.lights.len()} else {0};
+
+        Self {
+            settings: settings,
+            scene: scene,
+            aspect_ratio: settings.width as f32 / settings.height as f32,
+            material_ids: if settings.aovs.contains(Aov::MaterialId) {aov::material_ids(scene)} else {Vec::new()},
+            film: Film::new(settings.width, settings.height, settings.filter, 1 + filtered_aovs.len() + light_count),
+            filtered_aovs: filtered_aovs,
+            buffers: UnsafeBuffers::new(RenderBuffers::new(settings.width, settings.height, settings.aovs, scene.lights.len())),
+            cancel: cancel,
+            // limits too long for a duration or an instant can never be reached
+            deadline: settings.time_limit
+                .and_then(|seconds| Duration::try_from_secs_f32(seconds).ok())
+                .and_then(|limit| Instant::now().checked_add(limit))
+        }
+    }
+
+    fn should_stop(&self) -> bool {
+        self.cancel.is_cancelled() || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // runs `render` on every thread with the thread's sampler set up and sums their stats
+    fn run_threads<F>(&self, threads: usize, render: F) -> Stats
+        where F: Fn(&mut [Vector], &mut Stats) + Sync
+    {
+        let total_stats = Mutex::new(Stats::default());
+
+        crossbeam_utils::thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|_| {
+                    let mut stats = Stats {..Default::default()};
+                    let mut sample_values = vec![Vector::vec3(0.0, 0.0, 0.0); self.film.layers()];
+                    sampler::set_thread_sampler(Sampler::new(self.settings.sampler, self.settings.aa_samples * self.settings.aa_samples, self.settings.seed));
+                    render(&mut sample_values, &mut stats);
+                    *total_stats.lock().unwrap() += stats;
+                });
+            };
+        }).unwrap();
+
+        total_stats.into_inner().unwrap()
+    }
+
+    // takes the next sample of a pixel, `sample_values` holds the film layers of one sample
+    fn render_sample(&self, x: u32, y: u32, pixel: &mut PixelState, sample_values: &mut [Vector], stats: &mut Stats) {
+        let settings = self.settings;
+        sampler::start_sample(pixel.sample_count);
+        pixel.sample_count += 1;
+        stats.num_camera_samples += 1;
+
+        let pixel_offset = sample_2d();
+        let lens_sample = sample_2d();
+        let raster_x = x as f32 + pixel_offset.0;
+        let raster_y = y as f32 + pixel_offset.1;
+        let p_x = 2.0 * raster_x / settings.width as f32 - 1.0;
+        let p_y = 1.0 - 2.0 * raster_y / settings.height as f32;
+
+        let (values, light_values) = sample_values.split_at_mut(1 + self.filtered_aovs.len());
+        for value in light_values.iter_mut() {
+            *value = Vector::vec3(0.0, 0.0, 0.0);
+        }
+        let per_light = if light_values.is_empty() {None} else {Some(light_values)};
+
+        let sample = match self.scene.camera.generate_ray(p_x, p_y, self.aspect_ratio, lens_sample) {
+            Some((origin, direction)) => cast_camera_ray(origin, direction, self.scene, settings, per_light, stats),
+            None => CameraSample { color: Vector::vec3(0.0, 0.0, 0.0), hit: None, lighting: None }
+        };
+
+        values[0] = sample.color;
+        pixel.variance.add(sample.color);
+        for (layer, aov) in self.filtered_aovs.iter().enumerate() {
+            values[1 + layer] = aov.value(&sample, &self.material_ids);
+        }
+        self.film.add_sample(x, y, (raster_x, raster_y), sample_values);
+
+        // unfiltered aovs keep the sample nearest to the pixel center
+        let center_distance = (pixel_offset.0 - 0.5).powi(2) + (pixel_offset.1 - 0.5).powi(2);
+        if center_distance < pixel.closest_to_center {
+            pixel.closest_to_center = center_distance;
+            for aov in settings.aovs.iter().filter(|aov| !aov.is_filtered() && *aov != Aov::SampleCount) {
+                self.buffers.put_aov(x, y, aov, aov.value(&sample, &self.material_ids));
+            }
+        }
+
+        let count = pixel.sample_count as f32;
+        self.buffers.put_aov(x, y, Aov::SampleCount, Vector::vec3(count, count, count));
+    }
+
+    // the unfiltered aovs together with the film layers divided by their filter weights
+    fn resolve(&self) -> RenderBuffers {
+        let mut buffers = self.buffers.get().clone();
+        buffers.beauty = self.film.resolve(0);
+        for (layer, aov) in self.filtered_aovs.iter().enumerate() {
+            let framebuffer = self.film.resolve(1 + layer);
+            for (buffer_aov, buffer) in buffers.aovs.iter_mut() {
+                if buffer_aov == aov {
+                    *buffer = framebuffer;
+                    break;
+                }
+            }
+        }
+        for (light, framebuffer) in buffers.lights.iter_mut().enumerate() {
+            *framebuffer = self.film.resolve(1 + self.filtered_aovs.len() + light);
+        }
+        buffers
+    }
+}
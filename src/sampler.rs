@@ -0,0 +1,404 @@
+use std::cell::RefCell;
+use std::sync::OnceLock;
+
+/// Strategies for placing the samples of a pixel. Every sample draws its random numbers
+/// dimension by dimension, the pixel position first, then the lens and then whatever the
+/// shading asks for, and each sampler decides how those values are spread over the samples
+/// of a pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerType {
+    /// Independent uniform random numbers.
+    Random,
+    /// Jittered strata per dimension, shuffled so the dimensions don't line up.
+    Stratified,
+    /// Owen scrambled Halton sequence, one prime base per dimension.
+    Halton,
+    /// Owen scrambled and shuffled Sobol (0, 2) sequence for every pair of dimensions.
+    Sobol,
+    /// Sobol sequence shifted per pixel by a blue noise tile, so the remaining error looks
+    /// like fine grained blue noise instead of white noise at low sample counts.
+    BlueNoise
+}
+
+impl SamplerType {
+    pub fn from_name(name: &str) -> Option<SamplerType> {
+        match name.to_ascii_lowercase().as_str() {
+            "random" => Some(SamplerType::Random),
+            "stratified" => Some(SamplerType::Stratified),
+            "halton" => Some(SamplerType::Halton),
+            "sobol" => Some(SamplerType::Sobol),
+            "bluenoise" | "blue_noise" => Some(SamplerType::BlueNoise),
+            _ => None
+        }
+    }
+}
+
+/// Generates the sample values for one pixel sample at a time. Call `start_pixel` and
+/// `start_sample` before drawing values with `get_1d` and `get_2d`.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    pub sampler_type: SamplerType,
+    pub samples_per_pixel: u32,
+    seed: u64,
+    pixel: (u32, u32),
+    pixel_seed: u64,
+    sample_index: u32,
+    dimension: u32
+}
+
+impl Sampler {
+    /// Every pixel and sample gets its own stream derived from `seed`, so the values only
+    /// depend on the seed, the pixel and the sample index and not on which thread asks.
+    pub fn new(sampler_type: SamplerType, samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            sampler_type: sampler_type,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: seed,
+            pixel: (0, 0),
+            pixel_seed: 0,
+            sample_index: 0,
+            dimension: 0
+        }
+    }
+
+    pub fn start_pixel(&mut self, x: u32, y: u32) {
+        self.pixel = (x, y);
+        self.pixel_seed = hash(hash(self.seed, x as u64), y as u64);
+        self.start_sample(0);
+    }
+
+    pub fn start_sample(&mut self, sample_index: u32) {
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    pub fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let index = self.sample_index;
+        let dimension_seed = hash(self.pixel_seed, dimension as u64);
+
+        match self.sampler_type {
+            SamplerType::Random => to_unit_f32(dimension_seed, index),
+            SamplerType::Stratified => {
+                let count = self.samples_per_pixel;
+                if index >= count {
+                    return to_unit_f32(dimension_seed, index);
+                }
+                let stratum = permute(index, count, dimension_seed as u32);
+                (stratum as f32 + to_unit_f32(dimension_seed, index)) / count as f32
+            },
+            SamplerType::Halton => match PRIMES.get(dimension as usize) {
+                Some(base) => owen_scrambled_radical_inverse(*base, index, dimension_seed),
+                None => to_unit_f32(dimension_seed, index)
+            },
+            SamplerType::Sobol => {
+                let index = nested_uniform_scramble(index, dimension_seed as u32);
+                bits_to_f32(nested_uniform_scramble(index.reverse_bits(), (dimension_seed >> 32) as u32))
+            },
+            SamplerType::BlueNoise => {
+                let sequence_seed = hash(self.seed, dimension as u64);
+                let index = nested_uniform_scramble(index, sequence_seed as u32);
+                let value = bits_to_f32(nested_uniform_scramble(index.reverse_bits(), (sequence_seed >> 32) as u32));
+                fract(value + self.blue_noise_offset(dimension))
+            }
+        }
+    }
+
+    pub fn get_2d(&mut self) -> (f32, f32) {
+        let dimension = self.dimension;
+        let index = self.sample_index;
+        let dimension_seed = hash(self.pixel_seed, dimension as u64);
+
+        match self.sampler_type {
+            SamplerType::Stratified => {
+                self.dimension += 2;
+                let count = self.samples_per_pixel;
+                let jitter = (to_unit_f32(dimension_seed, index), to_unit_f32(dimension_seed ^ 1, index));
+                if index >= count {
+                    return jitter;
+                }
+
+                let per_axis = (count as f32).sqrt() as u32;
+                if per_axis * per_axis == count {
+                    let stratum = permute(index, count, dimension_seed as u32);
+                    (((stratum % per_axis) as f32 + jitter.0) / per_axis as f32, ((stratum / per_axis) as f32 + jitter.1) / per_axis as f32)
+                } else {
+                    // latin hypercube when the count has no square grid
+                    let stratum_x = permute(index, count, dimension_seed as u32);
+                    let stratum_y = permute(index, count, (dimension_seed >> 32) as u32);
+                    ((stratum_x as f32 + jitter.0) / count as f32, (stratum_y as f32 + jitter.1) / count as f32)
+                }
+            },
+            SamplerType::Sobol => {
+                self.dimension += 2;
+                sobol_2d(index, dimension_seed)
+            },
+            SamplerType::BlueNoise => {
+                self.dimension += 2;
+                let (x, y) = sobol_2d(index, hash(self.seed, dimension as u64));
+                (fract(x + self.blue_noise_offset(dimension)), fract(y + self.blue_noise_offset(dimension + 1)))
+            },
+            SamplerType::Random | SamplerType::Halton => (self.get_1d(), self.get_1d())
+        }
+    }
+
+    // every dimension reads the tile at a different offset so they stay uncorrelated
+    fn blue_noise_offset(&self, dimension: u32) -> f32 {
+        let tile = blue_noise_tile();
+        let offset = hash(self.seed ^ 0x5bd1_e995, dimension as u64);
+        let x = (self.pixel.0 as u64 + (offset & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        let y = (self.pixel.1 as u64 + ((offset >> 16) & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        tile[y * BLUE_NOISE_SIZE + x]
+    }
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Sampler> = RefCell::new(Sampler::new(SamplerType::Random, 1, 0));
+}
+
+/// Replaces the sampler of the calling thread, render threads set one up before they start.
+pub fn set_thread_sampler(sampler: Sampler) {
+    SAMPLER.with(|current| *current.borrow_mut() = sampler);
+}
+
+pub fn start_pixel(x: u32, y: u32) {
+    SAMPLER.with(|sampler| sampler.borrow_mut().start_pixel(x, y));
+}
+
+pub fn start_sample(sample_index: u32) {
+    SAMPLER.with(|sampler| sampler.borrow_mut().start_sample(sample_index));
+}
+
+/// Next value in [0, 1) from the sampler of the calling thread.
+#[inline]
+pub fn sample_1d() -> f32 {
+    SAMPLER.with(|sampler| sampler.borrow_mut().get_1d())
+}
+
+/// Next point in [0, 1)² from the sampler of the calling thread.
+#[inline]
+pub fn sample_2d() -> (f32, f32) {
+    SAMPLER.with(|sampler| sampler.borrow_mut().get_2d())
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 31;
+    x = x.wrapping_mul(0x7fb5_d329_728e_a185);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x81da_def4_bc2d_d44d);
+    x ^= x >> 33;
+    x
+}
+
+fn hash(seed: u64, value: u64) -> u64 {
+    mix(seed ^ mix(value.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+fn to_unit_f32(seed: u64, index: u32) -> f32 {
+    bits_to_f32(hash(seed, index as u64) as u32)
+}
+
+fn bits_to_f32(bits: u32) -> f32 {
+    ((bits >> 8) as f32 * (1.0 / (1u32 << 24) as f32)).min(ONE_MINUS_EPSILON)
+}
+
+fn fract(value: f32) -> f32 {
+    let value = value - value.floor();
+    value.min(ONE_MINUS_EPSILON)
+}
+
+// Kensler's hash based permutation of 0..count
+fn permute(mut i: u32, count: u32, p: u32) -> u32 {
+    let mut w = count - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < count {
+            break;
+        }
+    }
+
+    (i.wrapping_add(p)) % count
+}
+
+fn owen_scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f32 {
+    let inv_base = 1.0 / base as f64;
+    let mut digit_weight = inv_base;
+    let mut result = 0.0f64;
+    let mut prefix = 0u64;
+
+    // keep going after the index runs out of digits so the trailing zeros get scrambled too
+    while digit_weight > 1e-8 {
+        let digit = index % base;
+        let permuted = permute(digit, base, hash(seed, prefix) as u32);
+        result += permuted as f64 * digit_weight;
+        prefix = prefix.wrapping_mul(base as u64).wrapping_add(digit as u64 + 1);
+        index /= base;
+        digit_weight *= inv_base;
+    }
+
+    (result as f32).min(ONE_MINUS_EPSILON)
+}
+
+// Laine-Karras style hash that only lets lower bits affect higher ones, on bit reversed
+// values it becomes a nested uniform (Owen) scramble
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// the first two sobol dimensions, van der corput and the one with direction numbers
+// v_i = v_(i-1) ^ (v_(i-1) >> 1)
+fn sobol_pair(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut direction = 1u32 << 31;
+    let mut bits = index;
+
+    while bits != 0 {
+        if bits & 1 != 0 {
+            y ^= direction;
+        }
+        direction ^= direction >> 1;
+        bits >>= 1;
+    }
+
+    (index.reverse_bits(), y)
+}
+
+fn sobol_2d(index: u32, seed: u64) -> (f32, f32) {
+    let index = nested_uniform_scramble(index, seed as u32);
+    let (x, y) = sobol_pair(index);
+    let x = nested_uniform_scramble(x, hash(seed, 1) as u32);
+    let y = nested_uniform_scramble(y, hash(seed, 2) as u32);
+    (bits_to_f32(x), bits_to_f32(y))
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+fn blue_noise_tile() -> &'static [f32] {
+    static TILE: OnceLock<Vec<f32>> = OnceLock::new();
+    TILE.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.9))
+}
+
+// Ulichney's void and cluster method, ranks every cell of a tileable grid so that the cells
+// below any threshold are spread out evenly. Returns the ranks scaled to [0, 1).
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let count = size * size;
+
+    let mut kernel = vec![0.0f32; count];
+    for y in 0..size {
+        for x in 0..size {
+            let dx = x.min(size - x) as f32;
+            let dy = y.min(size - y) as f32;
+            kernel[y * size + x] = (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp();
+        }
+    }
+
+    let mut energy = vec![0.0f32; count];
+    let mut points = vec![false; count];
+    let update = |energy: &mut Vec<f32>, cell: usize, sign: f32| {
+        let (cx, cy) = (cell % size, cell / size);
+        for y in 0..size {
+            for x in 0..size {
+                let k = kernel[((y + size - cy) % size) * size + (x + size - cx) % size];
+                energy[y * size + x] += sign * k;
+            }
+        }
+    };
+    let tightest_cluster = |energy: &Vec<f32>, points: &Vec<bool>| {
+        (0..count).filter(|cell| points[*cell]).max_by(|a, b| energy[*a].partial_cmp(&energy[*b]).unwrap()).unwrap()
+    };
+    let largest_void = |energy: &Vec<f32>, points: &Vec<bool>| {
+        (0..count).filter(|cell| !points[*cell]).min_by(|a, b| energy[*a].partial_cmp(&energy[*b]).unwrap()).unwrap()
+    };
+
+    // random initial pattern, then move points from clusters into voids until it settles
+    let initial_count = count / 10;
+    let mut placed = 0;
+    let mut attempt = 0u64;
+    while placed < initial_count {
+        let cell = (hash(0x2545_f491_4f6c_dd1d, attempt) % count as u64) as usize;
+        attempt += 1;
+        if !points[cell] {
+            points[cell] = true;
+            update(&mut energy, cell, 1.0);
+            placed += 1;
+        }
+    }
+
+    for _ in 0..count {
+        let cluster = tightest_cluster(&energy, &points);
+        points[cluster] = false;
+        update(&mut energy, cluster, -1.0);
+
+        let void = largest_void(&energy, &points);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0usize; count];
+
+    // rank the initial points by taking out the tightest cluster first
+    let mut remove_energy = energy.clone();
+    let mut remove_points = points.clone();
+    for rank in (0..initial_count).rev() {
+        let cluster = tightest_cluster(&remove_energy, &remove_points);
+        remove_points[cluster] = false;
+        update(&mut remove_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // and the rest by filling the largest void
+    for rank in initial_count..count {
+        let void = largest_void(&energy, &points);
+        points[void] = true;
+        update(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map(|rank| (*rank as f32 + 0.5) / count as f32).collect()
+}
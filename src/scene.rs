@@ -1,28 +1,301 @@
-use crate::geometry::{Mesh, BoundingBox};
+use crate::geometry::{Mesh, Vertex, BoundingBox};
 use crate::shading::{materials::Material, lights::Lights};
-use crate::bvh::LinearBVHNode;
+use crate::bvh::{LinearBVHNode, MeshBvh, BvhSettings, BvhStats, build_bvh_with_settings};
 use crate::camera::Camera;
+use crate::matrix::Matrix;
+use crate::vector_simd::Vector;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 
 pub struct SceneData {
     pub bvh: Vec<LinearBVHNode>,
     pub object_indices: Vec<usize>,
     pub scene_objects: Vec<SceneObject>,
     pub lights: Vec<Lights>,
-    pub camera: Camera
+    pub camera: Camera,
+    /// How the bvhs were built when the scene was created.
+    pub bvh_stats: BvhStats,
+    /// Identifies the input the scene was loaded from, see `Fingerprint`. Scenes built in
+    /// code have 0 unless they set one.
+    pub fingerprint: u64,
+    /// Problems the loaders skipped over, like a missing material, for the caller to report.
+    pub warnings: Vec<String>
 }
 
-pub struct SceneObject {
+/// Hashes the input of a scene so a checkpoint can tell whether it is resumed with the same
+/// scene. Uses FNV-1a, which unlike the std hashers gives the same value on every run.
+#[derive(Clone, Copy, Debug)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    /// Adds a file the scene loads by its name as the scene refers to it and its contents.
+    /// A file that can't be read only adds its name, loading it reports the error.
+    pub fn add_file(&mut self, name: &str, path: &Path) {
+        self.add(name.as_bytes());
+        if let Ok(contents) = fs::read(path) {
+            self.add(&(contents.len() as u64).to_le_bytes());
+            self.add(&contents);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    NoObjects
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::NoObjects => write!(f, "the scene contains no objects")
+        }
+    }
+}
+
+/// Collects objects, lights and a camera and builds the bvh over them. The camera defaults
+/// to one at the origin looking down the negative z axis.
+pub struct SceneBuilder {
+    scene_objects: Vec<SceneObject>,
+    lights: Vec<Lights>,
+    camera: Camera,
+    bvh_settings: BvhSettings
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self {
+            scene_objects: Vec::new(),
+            lights: Vec::new(),
+            camera: Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0)),
+            bvh_settings: BvhSettings::default()
+        }
+    }
+
+    /// Adds a mesh placed in the world, rotation is in radians around x, y and z.
+    pub fn add_object(self, mesh: Mesh, material: Material, position: Vector, scale: Vector, rotation: Vector) -> Self {
+        self.add_scene_object(create_scene_object(mesh, material, position, scale, rotation))
+    }
+
+    pub fn add_object_with_matrix(self, mesh: Mesh, material: Material, world_matrix: Matrix) -> Self {
+        self.add_scene_object(create_scene_object_from_matrix(mesh, material, world_matrix))
+    }
+
+    /// Adds an instance of a shared mesh, the mesh is not copied.
+    pub fn add_instance(self, resource: &Arc<MeshResource>, material: Material, world_matrix: Matrix) -> Self {
+        self.add_scene_object(SceneObject::instance(resource.clone(), material, world_matrix))
+    }
+
+    pub fn add_scene_object(mut self, scene_object: SceneObject) -> Self {
+        self.scene_objects.push(scene_object);
+        self
+    }
+
+    pub fn add_light(mut self, light: Lights) -> Self {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    /// Settings for the bvh over the objects and the triangle bvhs of their meshes.
+    pub fn bvh_settings(mut self, settings: BvhSettings) -> Self {
+        self.bvh_settings = settings;
+        self
+    }
+
+    pub fn build(self) -> Result<SceneData, SceneError> {
+        if self.scene_objects.is_empty() {
+            return Err(SceneError::NoObjects);
+        }
+
+        let (bvh, indices, bvh_stats) = build_bvh_with_settings(&self.scene_objects, &self.bvh_settings);
+
+        Ok(SceneData {
+            bvh: bvh,
+            object_indices: indices,
+            scene_objects: self.scene_objects,
+            lights: self.lights,
+            camera: self.camera,
+            bvh_stats: bvh_stats,
+            fingerprint: 0,
+            warnings: Vec::new()
+        })
+    }
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A mesh in object space with the bvh over its triangles. Scene objects hold it through
+/// an `Arc`, so any number of instances can share one copy. The bvh is built together with
+/// the bvh of the scene the mesh is used in, with the same settings.
+pub struct MeshResource {
+    /// Where the mesh came from, like a file and object name, for messages and stats.
+    pub name: String,
     pub mesh: Mesh,
-    pub material: Material,
+    pub(crate) bvh: OnceLock<MeshBvh>,
     pub bounding_box: BoundingBox
 }
 
+impl MeshResource {
+    pub fn new(mesh: Mesh) -> Self {
+        Self::with_name(mesh, String::new())
+    }
+
+    pub fn with_name(mesh: Mesh, name: String) -> Self {
+        let mut bounding_box = BoundingBox::new();
+        for vertex in mesh.vertices.iter() {
+            bounding_box.extend_bounds(vertex.pos);
+        }
+
+        Self {
+            name: name,
+            mesh: mesh,
+            bvh: OnceLock::new(),
+            bounding_box: bounding_box
+        }
+    }
+
+    /// The bvh over the triangles, only available once the scene bvh has been built.
+    pub fn bvh(&self) -> &MeshBvh {
+        self.bvh.get().expect("mesh bvhs are built together with the scene bvh")
+    }
+}
+
+/// Object to world transform of an instance. Rays are moved into object space with the
+/// inverse, normals back into world space with the normal matrix.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub matrix: Matrix,
+    pub inverse: Matrix,
+    pub normal_matrix: Matrix
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix) -> Self {
+        let inverse = matrix.inverse();
+
+        Self {
+            matrix: matrix,
+            inverse: inverse,
+            normal_matrix: inverse.transpose()
+        }
+    }
+}
+
+pub struct SceneObject {
+    pub resource: Arc<MeshResource>,
+    pub material: Material,
+    /// Bounds in world space.
+    pub bounding_box: BoundingBox,
+    /// `None` when the vertices of the mesh are already in world space.
+    pub transform: Option<Transform>
+}
+
 impl SceneObject {
     pub fn new(mesh: Mesh, material: Material, bounding_box: BoundingBox) -> Self {
+        let resource = MeshResource::new(mesh);
+
         Self {
-            mesh: mesh,
+            resource: Arc::new(resource),
             material: material,
-            bounding_box: bounding_box
+            bounding_box: bounding_box,
+            transform: None
+        }
+    }
+
+    /// Places a shared mesh in the world without copying its vertices.
+    pub fn instance(resource: Arc<MeshResource>, material: Material, world_matrix: Matrix) -> Self {
+        let bounds = resource.bounding_box;
+        let mut bounding_box = BoundingBox::new();
+        for corner in 0..8 {
+            let x = if corner & 1 == 0 {bounds.min().x()} else {bounds.max().x()};
+            let y = if corner & 2 == 0 {bounds.min().y()} else {bounds.max().y()};
+            let z = if corner & 4 == 0 {bounds.min().z()} else {bounds.max().z()};
+            bounding_box.extend_bounds(Vector::vec3(x, y, z) * world_matrix);
+        }
+
+        Self {
+            resource: resource,
+            material: material,
+            bounding_box: bounding_box,
+            transform: Some(Transform::new(world_matrix))
+        }
+    }
+
+    pub fn mesh(&self) -> &Mesh {
+        &self.resource.mesh
+    }
+}
+
+pub fn create_scene_object(mesh: Mesh, material: Material, position:Vector, scale: Vector, rotation: Vector) -> SceneObject {
+    create_scene_object_from_matrix(mesh, material, world_matrix(position, scale, rotation))
+}
+
+/// Scales, then rotates around x, y and z in radians, then translates.
+pub fn world_matrix(position: Vector, scale: Vector, rotation: Vector) -> Matrix {
+    let scale_matrix = Matrix::scaling_matrix(scale);
+    let translation_matrix = Matrix::translation_matrix(position);
+    let rotation_matrix = Matrix::roatation_x(rotation.x()) * Matrix::roatation_y(rotation.y()) * Matrix::roatation_z(rotation.z());
+    scale_matrix * rotation_matrix * translation_matrix
+}
+
+pub fn create_scene_object_from_matrix(mesh: Mesh, material: Material, world_matrix: Matrix) -> SceneObject {
+    let inv_world = world_matrix.inverse().transpose();
+
+    let mut transformed_vertices = Vec::new();
+    let mut bounding_box = BoundingBox::new();
+
+    for i in 0..mesh.vertices.len() {
+        let vertex = Vertex::with_texture_coord(mesh.vertices[i].pos * world_matrix, mesh.vertices[i].normal * inv_world, mesh.vertices[i].texture_coord);
+        bounding_box.extend_bounds(vertex.pos);
+        transformed_vertices.push(vertex);
+    }
+
+    let mut indices = mesh.indices;
+
+    // mirroring transforms flip the winding, restore it so triangles still face outwards
+    if world_matrix.determinant() < 0.0 {
+        for triangle in indices.chunks_mut(3) {
+            triangle.swap(1, 2);
         }
     }
-}
\ No newline at end of file
+
+    let mesh_data = Mesh {
+        vertices: transformed_vertices,
+        indices: indices,
+        num_tris: mesh.num_tris,
+        colors: mesh.colors
+    };
+
+    SceneObject::new(mesh_data, material, bounding_box)
+}
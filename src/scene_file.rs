@@ -0,0 +1,883 @@
+// Text scene description format.
+//
+// A scene file is a list of blocks. Values are separated by whitespace and
+// anything following a '#' up to the end of the line is a comment:
+//
+//     settings {
+//         resolution 1280 720
+//         max_ray_depth 2
+//         diffuse_samples 4
+//         specular_samples 4
+//         aa_samples 7
+//         background 0.86 0.92 1.0
+//         seed 0
+//         sampler sobol               # random, stratified, halton, sobol or bluenoise
+//         adaptive_threshold 0.01     # relative error adaptive sampling stops at
+//         max_samples 256             # samples per pixel adaptive sampling stops at
+//         time_limit 600              # seconds, renders until done when left out
+//         filter box                  # box, tent, gaussian, mitchell or blackman_harris
+//         filter_radius 0.5           # pixels, defaults to the radius of the filter
+//         tone_map aces               # clamp, reinhard, aces or filmic, defaults to clamp
+//         exposure 0                  # stops
+//         aov depth                   # repeat for every aov to render
+//         bvh_bins 12                 # bins the bvh builders sort primitives into
+//         bvh_leaf_size 4             # bvh nodes with more primitives are always split
+//     }
+//
+// The aovs are depth, normal, albedo, position, object_id, material_id, the lighting passes
+// direct_diffuse, direct_specular, indirect_diffuse, indirect_specular and background,
+// sample_count, `lights` for one pass per light or `all`.
+//
+//     camera {
+//         position 0 0 0
+//         target 0 0 -1
+//         fov 40                      # degrees
+//     }
+//
+// Setting `ortho_width <width>` in the camera block instead of a fov makes an orthographic
+// camera that sees `width` world units across the image. Depth of field is enabled with
+// `aperture <radius>` or `f_stop <number>`, the camera focuses on its target unless
+// `focal_distance <distance>` is set. `blades <count>` and `blade_rotation <degrees>` shape
+// the aperture into a polygon for polygonal bokeh.
+//
+// `projection equirectangular`, `projection fisheye` and `projection cubemap` select the
+// panoramic projections. A fisheye uses `fov` for its image circle, 180 degrees by default,
+// and `fisheye_mapping equidistant` or `fisheye_mapping equisolid`.
+//
+//     material gold {
+//         albedo 0 0 0
+//         specular 1.0 0.782 0.344
+//         roughness 0.1
+//         metalness 1
+//     }
+//
+//     object {
+//         mesh sphere 0.4 20 20       # sphere <radius> <slices> <stacks>
+//         material gold               # or an inline material { ... } block
+//         position 1.0 -0.3 -2.0
+//         scale 0.5 0.5 0.5
+//         rotation 0 30 0             # degrees around x, y and z
+//     }
+//
+//     light directional {
+//         direction 0 -0.6 -1
+//         brightness 1.5
+//         color 1 1 1
+//     }
+//
+// Supported meshes are `sphere <radius> <slices> <stacks>`, `box <width> <height> <depth>`,
+// `plane <width> <depth> <subdivisions width> <subdivisions depth>`, `triangle`, `obj "<path>"`
+// and `ply "<path>"`. Paths are relative to the scene file. An obj file creates one object per
+// group and material, using the materials from its mtl library unless the object sets one.
+// Objects that load the same file, or create a mesh with the same parameters, share one copy
+// of it and only keep their own transform.
+// Supported lights are `directional`, `point` and `rectangular`.
+
+use crate::geometry::*;
+use crate::scene::*;
+use crate::shading::{materials::Material, lights::*};
+use crate::camera::{Camera, Lens, ProjectionType, Fisheye, FisheyeMapping};
+use crate::bvh::build_bvh_with_settings;
+use crate::loaders::obj::load_obj;
+use crate::loaders::ply::load_ply;
+use crate::math::degree_to_radians;
+use crate::vector_simd::Vector;
+use crate::tonemap::ToneMapOperator;
+use crate::sampler::SamplerType;
+use crate::filter::{FilterType, PixelFilter};
+use crate::adaptive::AdaptiveSampling;
+use crate::RenderSettings;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(ParseError)
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneFileError::Io(error) => write!(f, "could not read scene file: {}", error),
+            SceneFileError::Parse(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl From<io::Error> for SceneFileError {
+    fn from(error: io::Error) -> Self {
+        SceneFileError::Io(error)
+    }
+}
+
+impl From<ParseError> for SceneFileError {
+    fn from(error: ParseError) -> Self {
+        SceneFileError::Parse(error)
+    }
+}
+
+pub fn load_scene_file<P: AsRef<Path>>(path: P) -> Result<(SceneData, RenderSettings), SceneFileError> {
+    load_scene_file_with(path, |_| ())
+}
+
+/// Loads a scene file and applies `adjust` to its settings before the bvhs are built, so
+/// settings given elsewhere, like on the command line, can override the ones of the file.
+pub fn load_scene_file_with<P, F>(path: P, adjust: F) -> Result<(SceneData, RenderSettings), SceneFileError>
+    where P: AsRef<Path>, F: FnOnce(&mut RenderSettings)
+{
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let scene = parse_scene_with(&source, base_dir, adjust)?;
+    Ok(scene)
+}
+
+/// Parses a scene description, relative paths inside it are resolved against `base_dir`.
+pub fn parse_scene(source: &str, base_dir: &Path) -> Result<(SceneData, RenderSettings), ParseError> {
+    parse_scene_with(source, base_dir, |_| ())
+}
+
+/// Parses a scene description like `parse_scene` and applies `adjust` to its settings
+/// before the bvhs are built.
+pub fn parse_scene_with<F>(source: &str, base_dir: &Path, adjust: F) -> Result<(SceneData, RenderSettings), ParseError>
+    where F: FnOnce(&mut RenderSettings)
+{
+    let tokens = tokenize(source)?;
+    let mut parser = Parser::new(tokens, base_dir.to_path_buf());
+    parser.parse(adjust)
+}
+
+#[derive(Clone)]
+enum ObjectMesh {
+    Single(Arc<MeshResource>),
+    Multiple(Vec<(Arc<MeshResource>, Material)>)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    OpenBrace,
+    CloseBrace
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "'{}'", word),
+            TokenKind::Str(string) => write!(f, "\"{}\"", string),
+            TokenKind::OpenBrace => write!(f, "'{{'"),
+            TokenKind::CloseBrace => write!(f, "'}}'")
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    let mut column = 1;
+
+    while let Some(&c) = chars.peek() {
+        let start_line = line;
+        let start_column = column;
+
+        if c == '\n' {
+            chars.next();
+            line += 1;
+            column = 1;
+        } else if c.is_whitespace() {
+            chars.next();
+            column += 1;
+        } else if c == '#' {
+            while let Some(&c) = chars.peek() {
+                if c == '\n' {
+                    break;
+                }
+                chars.next();
+                column += 1;
+            }
+        } else if c == '{' || c == '}' {
+            chars.next();
+            column += 1;
+            let kind = if c == '{' {TokenKind::OpenBrace} else {TokenKind::CloseBrace};
+            tokens.push(Token { kind: kind, line: start_line, column: start_column });
+        } else if c == '"' {
+            chars.next();
+            column += 1;
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => {
+                        column += 1;
+                        break;
+                    },
+                    Some('\n') | None => {
+                        return Err(ParseError { line: start_line, column: start_column, message: "unterminated string".to_string() });
+                    },
+                    Some(c) => {
+                        column += 1;
+                        string.push(c);
+                    }
+                }
+            }
+            tokens.push(Token { kind: TokenKind::Str(string), line: start_line, column: start_column });
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '{' || c == '}' || c == '#' || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+                column += 1;
+            }
+            tokens.push(Token { kind: TokenKind::Word(word), line: start_line, column: start_column });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    base_dir: PathBuf,
+    position: usize,
+    end_line: usize,
+    end_column: usize,
+    // meshes already created, by the tokens that describe them
+    meshes: HashMap<String, ObjectMesh>,
+    // every block but the settings, which checkpoints compare on their own, and the mesh
+    // files the blocks load
+    fingerprint: Fingerprint,
+    warnings: Vec<String>
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, base_dir: PathBuf) -> Self {
+        let (end_line, end_column) = match tokens.last() {
+            Some(token) => (token.line, token.column + 1),
+            None => (1, 1)
+        };
+
+        Self {
+            tokens: tokens,
+            base_dir: base_dir,
+            position: 0,
+            end_line: end_line,
+            end_column: end_column,
+            meshes: HashMap::new(),
+            fingerprint: Fingerprint::new(),
+            warnings: Vec::new()
+        }
+    }
+
+    fn parse<F: FnOnce(&mut RenderSettings)>(&mut self, adjust: F) -> Result<(SceneData, RenderSettings), ParseError> {
+        let mut settings = RenderSettings::default();
+        let mut camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
+        let mut materials = HashMap::new();
+        let mut scene_objects = Vec::new();
+        let mut lights = Vec::new();
+
+        while let Some(token) = self.next_token() {
+            let start = self.position - 1;
+            match token.kind {
+                TokenKind::Word(ref keyword) if keyword == "settings" => {
+                    settings = self.parse_settings()?;
+                    continue;
+                },
+                TokenKind::Word(ref keyword) if keyword == "camera" => camera = self.parse_camera()?,
+                TokenKind::Word(ref keyword) if keyword == "material" => {
+                    let name = self.expect_name()?;
+                    let material = self.parse_material()?;
+                    materials.insert(name, material);
+                },
+                TokenKind::Word(ref keyword) if keyword == "object" => scene_objects.extend(self.parse_object(&materials)?),
+                TokenKind::Word(ref keyword) if keyword == "light" => lights.push(self.parse_light()?),
+                _ => return Err(Self::error_at(&token, format!("expected 'settings', 'camera', 'material', 'object' or 'light', found {}", token.kind)))
+            }
+
+            for token in self.tokens[start..self.position].iter() {
+                self.fingerprint.add(token.kind.to_string().as_bytes());
+                self.fingerprint.add(b" ");
+            }
+        }
+
+        if scene_objects.is_empty() {
+            return Err(ParseError { line: self.end_line, column: self.end_column, message: "scene contains no objects".to_string() });
+        }
+
+        adjust(&mut settings);
+        let (bvh, indices, bvh_stats) = build_bvh_with_settings(&scene_objects, &settings.bvh);
+
+        let scene = SceneData {
+            bvh: bvh,
+            object_indices: indices,
+            scene_objects: scene_objects,
+            lights: lights,
+            camera: camera,
+            bvh_stats: bvh_stats,
+            fingerprint: self.fingerprint.finish(),
+            warnings: std::mem::take(&mut self.warnings)
+        };
+
+        Ok((scene, settings))
+    }
+
+    fn parse_settings(&mut self) -> Result<RenderSettings, ParseError> {
+        let mut settings = RenderSettings::default();
+        let mut filter_radius = None;
+
+        self.expect_open_brace()?;
+        while let Some(key) = self.next_property()? {
+            match key.as_str() {
+                "resolution" => {
+                    settings.width = self.expect_positive_integer()?;
+                    settings.height = self.expect_positive_integer()?;
+                },
+                "max_ray_depth" => settings.max_ray_depth = self.expect_integer()?,
+                "diffuse_samples" => settings.diffuse_samples = self.expect_integer()?,
+                "specular_samples" => settings.specular_samples = self.expect_integer()?,
+                "aa_samples" => settings.aa_samples = self.expect_positive_integer()?,
+                "background" => settings.background_color = self.expect_vec3()?,
+                "seed" => settings.seed = self.expect_integer()? as u64,
+                "sampler" => {
+                    let name = self.expect_name()?;
+                    settings.sampler = match SamplerType::from_name(&name) {
+                        Some(sampler) => sampler,
+                        None => return Err(self.error_at_previous(format!("unknown sampler '{}', expected random, stratified, halton, sobol or bluenoise", name)))
+                    };
+                },
+                "adaptive_threshold" => {
+                    let threshold = self.expect_number()?;
+                    if threshold <= 0.0 {
+                        return Err(self.error_at_previous(format!("adaptive threshold must be positive, got {}", threshold)));
+                    }
+                    settings.adaptive.get_or_insert_with(AdaptiveSampling::default).threshold = threshold;
+                },
+                "max_samples" => settings.adaptive.get_or_insert_with(AdaptiveSampling::default).max_samples = self.expect_positive_integer()?,
+                "time_limit" => {
+                    let seconds = self.expect_number()?;
+                    if seconds <= 0.0 {
+                        return Err(self.error_at_previous(format!("time limit must be positive, got {}", seconds)));
+                    }
+                    settings.time_limit = Some(seconds);
+                },
+                "filter" => {
+                    let name = self.expect_name()?;
+                    settings.filter = match FilterType::from_name(&name) {
+                        Some(filter_type) => PixelFilter::new(filter_type),
+                        None => return Err(self.error_at_previous(format!("unknown filter '{}', expected box, tent, gaussian, mitchell or blackman_harris", name)))
+                    };
+                },
+                "filter_radius" => {
+                    let radius = self.expect_number()?;
+                    if radius < 0.5 {
+                        return Err(self.error_at_previous(format!("filter radius must be at least 0.5, got {}", radius)));
+                    }
+                    filter_radius = Some(radius);
+                },
+                "tone_map" => {
+                    let name = self.expect_name()?;
+                    settings.display.operator = match ToneMapOperator::from_name(&name) {
+                        Some(operator) => operator,
+                        None => return Err(self.error_at_previous(format!("unknown tone map '{}', expected clamp, reinhard, aces or filmic", name)))
+                    };
+                },
+                "exposure" => settings.display.exposure = self.expect_number()?,
+                "bvh_bins" => {
+                    let bins = self.expect_integer()?;
+                    if bins < 2 {
+                        return Err(self.error_at_previous(format!("a bvh needs at least 2 bins, got {}", bins)));
+                    }
+                    settings.bvh.bins = bins as usize;
+                },
+                "bvh_leaf_size" => settings.bvh.max_leaf_size = self.expect_positive_integer()? as usize,
+                "aov" => {
+                    let name = self.expect_name()?;
+                    if !settings.aovs.insert_name(&name) {
+                        return Err(self.error_at_previous(format!("unknown aov '{}'", name)));
+                    }
+                },
+                _ => return Err(self.error_at_previous(format!("unknown settings property '{}'", key)))
+            }
+        }
+
+        // the radius may come before the filter, which would reset it to its default
+        if let Some(radius) = filter_radius {
+            settings.filter.radius = radius;
+        }
+
+        Ok(settings)
+    }
+
+    fn parse_camera(&mut self) -> Result<Camera, ParseError> {
+        let camera_token = self.tokens[self.position - 1].clone();
+        let mut position = Vector::vec3(0.0, 0.0, 0.0);
+        let mut target = Vector::vec3(0.0, 0.0, -1.0);
+        let mut projection = None;
+        let mut fov = None;
+        let mut ortho_width = None;
+        let mut fisheye_mapping = FisheyeMapping::Equidistant;
+        let mut aperture = None;
+        let mut f_stop = None;
+        let mut focal_distance = None;
+        let mut blades = 0;
+        let mut blade_rotation = 0.0;
+
+        self.expect_open_brace()?;
+        while let Some(key) = self.next_property()? {
+            match key.as_str() {
+                "position" => position = self.expect_vec3()?,
+                "target" => target = self.expect_vec3()?,
+                "projection" => {
+                    let name = self.expect_name()?;
+                    match name.as_str() {
+                        "perspective" | "orthographic" | "equirectangular" | "fisheye" | "cubemap" => projection = Some(name),
+                        _ => return Err(self.error_at_previous(format!("unknown projection '{}', expected perspective, orthographic, equirectangular, fisheye or cubemap", name)))
+                    }
+                },
+                "ortho_width" => ortho_width = Some(self.expect_positive_number()?),
+                "fov" => {
+                    let degrees = self.expect_number()?;
+                    if degrees <= 0.0 || degrees > 360.0 {
+                        return Err(self.error_at_previous("fov must be between 0 and 360 degrees".to_string()));
+                    }
+                    fov = Some((degrees, self.tokens[self.position - 1].clone()));
+                },
+                "fisheye_mapping" => {
+                    fisheye_mapping = match self.expect_name()?.as_str() {
+                        "equidistant" => FisheyeMapping::Equidistant,
+                        "equisolid" => FisheyeMapping::Equisolid,
+                        name => return Err(self.error_at_previous(format!("unknown fisheye mapping '{}', expected equidistant or equisolid", name)))
+                    };
+                },
+                "aperture" => aperture = Some(self.expect_positive_number()?),
+                "f_stop" => f_stop = Some(self.expect_positive_number()?),
+                "focal_distance" => focal_distance = Some(self.expect_positive_number()?),
+                "blades" => {
+                    blades = self.expect_integer()?;
+                    if blades > 0 && blades < 3 {
+                        return Err(self.error_at_previous("an aperture needs at least 3 blades, use 0 for a round one".to_string()));
+                    }
+                },
+                "blade_rotation" => blade_rotation = degree_to_radians(self.expect_number()?),
+                _ => return Err(self.error_at_previous(format!("unknown camera property '{}'", key)))
+            }
+        }
+
+        let projection = match projection {
+            Some(projection) => projection,
+            None if ortho_width.is_some() => "orthographic".to_string(),
+            None => "perspective".to_string()
+        };
+
+        if ortho_width.is_some() && projection != "orthographic" {
+            return Err(Self::error_at(&camera_token, "'ortho_width' needs an orthographic camera".to_string()));
+        }
+
+        let fov_degrees = match fov {
+            Some((degrees, ref fov_token)) => {
+                if projection == "perspective" && degrees >= 180.0 {
+                    return Err(Self::error_at(fov_token, "a perspective fov must be less than 180 degrees".to_string()));
+                }
+                if projection != "perspective" && projection != "fisheye" {
+                    return Err(Self::error_at(fov_token, format!("a {} camera has no fov", projection)));
+                }
+                degrees
+            },
+            None if projection == "fisheye" => 180.0,
+            None => 40.0
+        };
+        let fov = degree_to_radians(fov_degrees);
+
+        let camera = match projection.as_str() {
+            "orthographic" => match ortho_width {
+                Some(ortho_width) => Camera::orthographic(position, target, ortho_width),
+                None => return Err(Self::error_at(&camera_token, "an orthographic camera needs an 'ortho_width'".to_string()))
+            },
+            "equirectangular" => Camera::with_projection(position, target, ProjectionType::Equirectangular),
+            "fisheye" => Camera::with_projection(position, target, ProjectionType::Fisheye(Fisheye { fov: fov, mapping: fisheye_mapping })),
+            "cubemap" => Camera::with_projection(position, target, ProjectionType::CubeMap),
+            _ => Camera::with_fov(position, target, fov)
+        };
+
+        if (aperture.is_some() || f_stop.is_some()) && projection != "perspective" && projection != "orthographic" {
+            return Err(Self::error_at(&camera_token, format!("depth of field is not supported by a {} camera", projection)));
+        }
+
+        let lens = match (aperture, f_stop) {
+            (Some(_), Some(_)) => return Err(Self::error_at(&camera_token, "a camera can't set both 'aperture' and 'f_stop'".to_string())),
+            (Some(aperture), None) => Lens::new(aperture),
+            (None, Some(f_stop)) => {
+                if projection != "perspective" {
+                    return Err(Self::error_at(&camera_token, "'f_stop' needs a perspective camera, use 'aperture' instead".to_string()));
+                }
+                Lens::from_f_stop(f_stop, fov)
+            },
+            (None, None) => return Ok(camera)
+        };
+
+        Ok(camera.with_lens(Lens {
+            focal_distance: focal_distance,
+            blades: blades,
+            blade_rotation: blade_rotation,
+            ..lens
+        }))
+    }
+
+    fn parse_material(&mut self) -> Result<Material, ParseError> {
+        let mut material = Material::default();
+
+        self.expect_open_brace()?;
+        while let Some(key) = self.next_property()? {
+            match key.as_str() {
+                "albedo" => material.albedo = self.expect_vec3()?,
+                "specular" => material.specular = self.expect_vec3()?,
+                "roughness" => material.roughness = self.expect_number()?,
+                "ior" => material.ior = self.expect_number()?,
+                "transmission" => material.transmission = self.expect_number()?,
+                "metalness" => material.metalicness = self.expect_number()?,
+                _ => return Err(self.error_at_previous(format!("unknown material property '{}'", key)))
+            }
+        }
+
+        Ok(material)
+    }
+
+    fn parse_object(&mut self, materials: &HashMap<String, Material>) -> Result<Vec<SceneObject>, ParseError> {
+        let object_token = self.tokens[self.position - 1].clone();
+
+        let mut mesh = None;
+        let mut material = None;
+        let mut position = Vector::vec3(0.0, 0.0, 0.0);
+        let mut scale = Vector::vec3(1.0, 1.0, 1.0);
+        let mut rotation = Vector::vec3(0.0, 0.0, 0.0);
+
+        self.expect_open_brace()?;
+        while let Some(key) = self.next_property()? {
+            match key.as_str() {
+                "mesh" => mesh = Some(self.parse_mesh()?),
+                "material" => {
+                    if self.peek_kind() == Some(&TokenKind::OpenBrace) {
+                        material = Some(self.parse_material()?);
+                    } else {
+                        let name = self.expect_name()?;
+                        match materials.get(&name) {
+                            Some(m) => material = Some(*m),
+                            None => return Err(self.error_at_previous(format!("undefined material '{}'", name)))
+                        }
+                    }
+                },
+                "position" => position = self.expect_vec3()?,
+                "scale" => scale = self.expect_vec3()?,
+                "rotation" => {
+                    let degrees = self.expect_vec3()?;
+                    rotation = Vector::vec3(degree_to_radians(degrees.x()), degree_to_radians(degrees.y()), degree_to_radians(degrees.z()));
+                },
+                _ => return Err(self.error_at_previous(format!("unknown object property '{}'", key)))
+            }
+        }
+
+        let mesh = match mesh {
+            Some(mesh) => mesh,
+            None => return Err(Self::error_at(&object_token, "object is missing a 'mesh'".to_string()))
+        };
+
+        let world = world_matrix(position, scale, rotation);
+        match mesh {
+            ObjectMesh::Single(resource) => {
+                let material = match material {
+                    Some(material) => material,
+                    None => return Err(Self::error_at(&object_token, "object is missing a 'material'".to_string()))
+                };
+
+                Ok(vec![SceneObject::instance(resource, material, world)])
+            },
+            ObjectMesh::Multiple(meshes) => {
+                Ok(meshes.into_iter()
+                    .map(|(resource, mesh_material)| SceneObject::instance(resource, material.unwrap_or(mesh_material), world))
+                    .collect())
+            }
+        }
+    }
+
+    fn parse_mesh(&mut self) -> Result<ObjectMesh, ParseError> {
+        let start = self.position;
+        let kind = self.expect_name()?;
+
+        if kind == "obj" || kind == "ply" {
+            let path = self.expect_name()?;
+            let key = format!("{} {}", kind, path);
+            if let Some(mesh) = self.meshes.get(&key) {
+                return Ok(mesh.clone());
+            }
+
+            let mesh = if kind == "obj" {self.load_obj_mesh(&path)?} else {self.load_ply_mesh(&path)?};
+            self.meshes.insert(key, mesh.clone());
+            return Ok(mesh);
+        }
+
+        let mesh = match kind.as_str() {
+            "sphere" => {
+                let radius = self.expect_number()?;
+                let slices = self.expect_integer()?;
+                if slices < 3 {
+                    return Err(self.error_at_previous("a sphere needs at least 3 slices".to_string()));
+                }
+                let stacks = self.expect_integer()?;
+                if stacks < 2 {
+                    return Err(self.error_at_previous("a sphere needs at least 2 stacks".to_string()));
+                }
+                create_sphere(radius, slices, stacks)
+            },
+            "box" => {
+                let width = self.expect_number()?;
+                let height = self.expect_number()?;
+                let depth = self.expect_number()?;
+                create_box(width, height, depth)
+            },
+            "plane" => {
+                let width = self.expect_number()?;
+                let depth = self.expect_number()?;
+                let sub_div_width = self.expect_integer()?;
+                if sub_div_width < 2 {
+                    return Err(self.error_at_previous("a plane needs at least 2 subdivisions".to_string()));
+                }
+                let sub_div_depth = self.expect_integer()?;
+                if sub_div_depth < 2 {
+                    return Err(self.error_at_previous("a plane needs at least 2 subdivisions".to_string()));
+                }
+                create_plane(width, depth, sub_div_width, sub_div_depth)
+            },
+            "triangle" => create_triangle(),
+            _ => return Err(self.error_at_previous(format!("unknown mesh type '{}', expected 'sphere', 'box', 'plane', 'triangle', 'obj' or 'ply'", kind)))
+        };
+
+        let key = self.tokens[start..self.position].iter().map(|token| match token.kind {
+            TokenKind::Word(ref word) => word.clone(),
+            ref kind => kind.to_string()
+        }).collect::<Vec<_>>().join(" ");
+        let mesh = self.meshes.entry(key.clone()).or_insert_with(|| ObjectMesh::Single(Arc::new(MeshResource::with_name(mesh, key))));
+        Ok(mesh.clone())
+    }
+
+    fn load_obj_mesh(&mut self, path: &str) -> Result<ObjectMesh, ParseError> {
+        self.fingerprint.add_file(path, &self.base_dir.join(path));
+        let file = match load_obj(self.base_dir.join(path)) {
+            Ok(file) => file,
+            Err(error) => return Err(self.error_at_previous(format!("could not load '{}': {}", path, error)))
+        };
+
+        self.warnings.extend(file.warnings.iter().map(|warning| format!("{}: {}", path, warning)));
+        if file.objects.is_empty() {
+            return Err(self.error_at_previous(format!("'{}' contains no faces", path)));
+        }
+
+        let mut meshes = Vec::new();
+        for object in file.objects {
+            let resource = MeshResource::with_name(object.mesh, format!("{} from {}", object.name, path));
+            meshes.push((Arc::new(resource), object.material));
+        }
+
+        Ok(ObjectMesh::Multiple(meshes))
+    }
+
+    fn load_ply_mesh(&mut self, path: &str) -> Result<ObjectMesh, ParseError> {
+        self.fingerprint.add_file(path, &self.base_dir.join(path));
+        let mesh = match load_ply(self.base_dir.join(path)) {
+            Ok(mesh) => mesh,
+            Err(error) => return Err(self.error_at_previous(format!("could not load '{}': {}", path, error)))
+        };
+
+        if mesh.num_tris == 0 {
+            return Err(self.error_at_previous(format!("'{}' contains no faces", path)));
+        }
+
+        Ok(ObjectMesh::Single(Arc::new(MeshResource::with_name(mesh, path.to_string()))))
+    }
+
+    fn parse_light(&mut self) -> Result<Lights, ParseError> {
+        let kind = self.expect_name()?;
+        let kind_token = self.tokens[self.position - 1].clone();
+
+        let mut position = None;
+        let mut direction = None;
+        let mut brightness = 1.0;
+        let mut color = Vector::vec3(1.0, 1.0, 1.0);
+        let mut range = 10.0;
+        let mut attenuation = None;
+        let mut width = 1.0;
+        let mut height = 1.0;
+        let mut samples = 1;
+
+        if kind != "directional" && kind != "point" && kind != "rectangular" {
+            return Err(Self::error_at(&kind_token, format!("unknown light type '{}', expected 'directional', 'point' or 'rectangular'", kind)));
+        }
+
+        self.expect_open_brace()?;
+        while let Some(key) = self.next_property()? {
+            match (kind.as_str(), key.as_str()) {
+                (_, "brightness") => brightness = self.expect_number()?,
+                (_, "color") => color = self.expect_vec3()?,
+                ("directional", "direction") | ("rectangular", "direction") => direction = Some(self.expect_vec3()?),
+                ("point", "position") | ("rectangular", "position") => position = Some(self.expect_vec3()?),
+                ("point", "range") | ("rectangular", "range") => range = self.expect_number()?,
+                ("point", "attenuation") | ("rectangular", "attenuation") => attenuation = Some(self.expect_vec3()?),
+                ("rectangular", "width") => width = self.expect_number()?,
+                ("rectangular", "height") => height = self.expect_number()?,
+                ("rectangular", "samples") => samples = self.expect_positive_integer()?,
+                _ => return Err(self.error_at_previous(format!("unknown {} light property '{}'", kind, key)))
+            }
+        }
+
+        let light = match kind.as_str() {
+            "directional" => {
+                let direction = Self::required(direction, &kind_token, "a directional light needs a 'direction'")?;
+                Lights::Directional(DirectionalLight::new(direction, brightness, color))
+            },
+            "point" => {
+                let position = Self::required(position, &kind_token, "a point light needs a 'position'")?;
+                let attenuation = attenuation.unwrap_or_else(|| Vector::vec3(0.0, 0.0, 1.0));
+                Lights::Point(PointLight::new(position, brightness, color, range, attenuation))
+            },
+            _ => {
+                let position = Self::required(position, &kind_token, "a rectangular light needs a 'position'")?;
+                let direction = Self::required(direction, &kind_token, "a rectangular light needs a 'direction'")?;
+                let attenuation = attenuation.unwrap_or_else(|| Vector::vec3(1.0, 1.0, 1.0));
+                Lights::Rectangular(RectangularLight::new(position, direction, width, height, samples, brightness, color, range, attenuation))
+            }
+        };
+
+        Ok(light)
+    }
+
+    fn required(value: Option<Vector>, token: &Token, message: &str) -> Result<Vector, ParseError> {
+        match value {
+            Some(value) => Ok(value),
+            None => Err(Self::error_at(token, message.to_string()))
+        }
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.position).map(|token| &token.kind)
+    }
+
+    fn expect_token(&mut self, expected: &str) -> Result<Token, ParseError> {
+        match self.next_token() {
+            Some(token) => Ok(token),
+            None => Err(ParseError { line: self.end_line, column: self.end_column, message: format!("expected {}, found end of file", expected) })
+        }
+    }
+
+    fn expect_open_brace(&mut self) -> Result<(), ParseError> {
+        let token = self.expect_token("'{'")?;
+        match token.kind {
+            TokenKind::OpenBrace => Ok(()),
+            _ => Err(Self::error_at(&token, format!("expected '{{', found {}", token.kind)))
+        }
+    }
+
+    /// Returns the next property name inside a block or `None` once the closing brace is reached.
+    fn next_property(&mut self) -> Result<Option<String>, ParseError> {
+        let token = self.expect_token("a property or '}'")?;
+        match token.kind {
+            TokenKind::CloseBrace => Ok(None),
+            TokenKind::Word(word) => Ok(Some(word)),
+            _ => Err(Self::error_at(&token, format!("expected a property or '}}', found {}", token.kind)))
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<String, ParseError> {
+        let token = self.expect_token("a name")?;
+        match token.kind {
+            TokenKind::Word(word) | TokenKind::Str(word) => Ok(word),
+            _ => Err(Self::error_at(&token, format!("expected a name, found {}", token.kind)))
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<f32, ParseError> {
+        let token = self.expect_token("a number")?;
+        if let TokenKind::Word(ref word) = token.kind {
+            if let Ok(value) = word.parse::<f32>() {
+                if value.is_finite() {
+                    return Ok(value);
+                }
+            }
+        }
+        Err(Self::error_at(&token, format!("expected a number, found {}", token.kind)))
+    }
+
+    fn expect_integer(&mut self) -> Result<u32, ParseError> {
+        let token = self.expect_token("an integer")?;
+        if let TokenKind::Word(ref word) = token.kind {
+            if let Ok(value) = word.parse::<u32>() {
+                return Ok(value);
+            }
+        }
+        Err(Self::error_at(&token, format!("expected a non-negative integer, found {}", token.kind)))
+    }
+
+    fn expect_positive_integer(&mut self) -> Result<u32, ParseError> {
+        let value = self.expect_integer()?;
+        if value == 0 {
+            return Err(self.error_at_previous("expected a value greater than 0".to_string()));
+        }
+        Ok(value)
+    }
+
+    fn expect_positive_number(&mut self) -> Result<f32, ParseError> {
+        let value = self.expect_number()?;
+        if value <= 0.0 {
+            return Err(self.error_at_previous("expected a value greater than 0".to_string()));
+        }
+        Ok(value)
+    }
+
+    fn expect_vec3(&mut self) -> Result<Vector, ParseError> {
+        let x = self.expect_number()?;
+        let y = self.expect_number()?;
+        let z = self.expect_number()?;
+        Ok(Vector::vec3(x, y, z))
+    }
+
+    fn error_at(token: &Token, message: String) -> ParseError {
+        ParseError { line: token.line, column: token.column, message: message }
+    }
+
+    fn error_at_previous(&self, message: String) -> ParseError {
+        Self::error_at(&self.tokens[self.position - 1], message)
+    }
+}
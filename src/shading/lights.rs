@@ -11,7 +11,7 @@ pub struct LightColorInfo {
 
 impl LightColorInfo {
     pub fn intensity(&self) -> Vector {
-        let exp = if self.exposure < 1 {1.0} else {(2.0 as f32).powi(self.exposure)};
+        let exp = if self.exposure < 1 {1.0} else {2.0_f32.powi(self.exposure)};
         self.color * self.brightness * exp        
     }
 }
@@ -84,6 +84,7 @@ impl PointLight {
 }
 
 impl RectangularLight {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(pos: Vector, dir: Vector, width: f32, height: f32, samples: u32, brightness: f32, color: Vector, range: f32, attenuation: Vector) -> Self {
         let up = Vector::vec3(0.0, 1.0, 0.0);
 
@@ -21,4 +21,10 @@ impl Material {
             metalicness: metalicness
         }
     }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::new(Vector::vec3(0.8, 0.8, 0.8), Vector::vec3(0.04, 0.04, 0.04), 0.5, 1.0, 0.0, 0.0)
+    }
 }
\ No newline at end of file
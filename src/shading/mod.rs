@@ -1,22 +1,22 @@
-#![allow(dead_code)]
 pub mod lights;
 pub mod materials;
 mod brdf;
-mod monte_carlo;
+pub(crate) mod monte_carlo;
 
 use self::materials::Material;
 use self::lights::Lights;
 use self::brdf::*;
 use self::monte_carlo::*;
 
-use crate::{Vector, ray_tracer::*, scene::*, Stats, RenderSettings, matrix::Matrix, geometry::*, math::*};
+use crate::{Vector, ray_tracer::*, scene::*, Stats, RenderSettings, matrix::Matrix, geometry::*, math::*, sampler::sample_2d};
 
 use std::{f32, f32::consts};
-use rand::Rng;
 
 pub struct ShadingData {
     position: Vector,
     normal: Vector,
+    // no material is textured yet
+    #[allow(dead_code)]
     texture_coord: Vector,
     material: Material
 }
@@ -32,39 +32,81 @@ impl ShadingData {
     }
 }
 
+/// Light leaving a surface split by how it got there. Direct light comes straight from the
+/// lights, indirect light bounced off other surfaces first.
+#[derive(Clone, Copy, Debug)]
+pub struct LightingComponents {
+    diffuse_reflectance: Vector,
+    direct_diffuse: Vector,
+    direct_specular: Vector,
+    indirect_diffuse: Vector,
+    indirect_specular: Vector
+}
+
+impl LightingComponents {
+    pub fn direct_diffuse(&self) -> Vector {
+        self.diffuse_reflectance * self.direct_diffuse
+    }
+
+    pub fn direct_specular(&self) -> Vector {
+        self.direct_specular
+    }
+
+    pub fn indirect_diffuse(&self) -> Vector {
+        self.diffuse_reflectance * self.indirect_diffuse
+    }
+
+    pub fn indirect_specular(&self) -> Vector {
+        self.indirect_specular
+    }
+
+    pub fn total(&self) -> Vector {
+        self.diffuse_reflectance * (self.direct_diffuse + self.indirect_diffuse) + self.direct_specular + self.indirect_specular
+    }
+}
+
 pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, stats: & mut Stats) -> Vector {
+    calculate_lighting(data, dir, scene, current_ray_depth, settings, ray_type, None, stats).total()
+}
+
+/// Shades a surface like `calculate_color` but keeps the lighting components apart. The
+/// direct light of every light is added to `per_light` when it is given.
+#[allow(clippy::too_many_arguments)]
+pub fn calculate_lighting(data: ShadingData, dir: Vector, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, mut per_light: Option<&mut [Vector]>, stats: & mut Stats) -> LightingComponents {
     let mut diffuse = Vector::vec3(0.0, 0.0, 0.0);
     let mut specular = Vector::vec3(0.0, 0.0, 0.0);
 
     let lights = &scene.lights;
+    let diffuse_reflectance = data.material.albedo / consts::PI;
 
     for i in 0..lights.len() {
+        let diffuse_before = diffuse;
+        let specular_before = specular;
+
         match &lights[i] {
             Lights::Directional(light) => {  
                 let l = -(light.direction.vec3_normalize());
-                match trace(data.position + data.normal * 0.0001, l, &scene.scene_objects, &scene.bvh, &scene.object_indices, f32::INFINITY, current_ray_depth + 1, settings, RayType::ShadowRay, stats) {
-                    None => {
-                        let v = -dir;
-                        let n = data.normal;
-
-                        compute_lighting(data.material.roughness, data.material.specular, n, v, l, 1.0, light.intensity(), &mut diffuse, &mut specular);
-                    },
-                    _ => ()
+                if !occluded(data.position + data.normal * 0.0001, l, scene, 0.0, f32::INFINITY, current_ray_depth + 1, settings, stats) {
+                    let v = -dir;
+                    let n = data.normal;
+
+                    let (light_diffuse, light_specular) = compute_lighting(data.material.roughness, data.material.specular, n, v, l, 1.0, light.intensity());
+                    diffuse += light_diffuse;
+                    specular += light_specular;
                 }
             },
             Lights::Point(light) => {
                 let mut l = light.position - data.position;
                 let distance = l.vec3_length_f32();
                 l /= distance;      
-                match trace(data.position + data.normal * 0.0001, l, &scene.scene_objects, &scene.bvh, &scene.object_indices, distance, current_ray_depth + 1, settings, RayType::ShadowRay, stats) {
-                    None => {
-                        let v = -dir;
-                        let n = data.normal;
-
-                        let falloff = 4.0 * consts::PI * distance * distance;
-                        compute_lighting(data.material.roughness, data.material.specular, n, v, l, falloff, light.intensity(), &mut diffuse, &mut specular);
-                    },
-                    _ => ()
+                if !occluded(data.position + data.normal * 0.0001, l, scene, 0.0, distance, current_ray_depth + 1, settings, stats) {
+                    let v = -dir;
+                    let n = data.normal;
+
+                    let falloff = 4.0 * consts::PI * distance * distance;
+                    let (light_diffuse, light_specular) = compute_lighting(data.material.roughness, data.material.specular, n, v, l, falloff, light.intensity());
+                    diffuse += light_diffuse;
+                    specular += light_specular;
                 }
             }
             Lights::Rectangular(light) => {
@@ -78,13 +120,12 @@ pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, curren
                     samples = 1;
                 }
 
-                let t;
                 let n = data.normal;
-                if n.x().abs() > n.y().abs() {
-                    t = Vector::vec3(n.z(), 0.0, -n.x()) / (n.x() * n.x() + n.z() * n.z()).sqrt();
+                let t = if n.x().abs() > n.y().abs() {
+                    Vector::vec3(n.z(), 0.0, -n.x()) / (n.x() * n.x() + n.z() * n.z()).sqrt()
                 } else {
-                    t = Vector::vec3(0.0, -n.z(), n.y()) / (n.y() * n.y() + n.z() * n.z()).sqrt();
-                }
+                    Vector::vec3(0.0, -n.z(), n.y()) / (n.y() * n.y() + n.z() * n.z()).sqrt()
+                };
             
                 let b = n.vec3_cross(t);
             
@@ -93,8 +134,7 @@ pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, curren
                 );
 
                 for _ in 0..samples {
-                    let rand1 = rand::thread_rng().gen_range(0.0, 1.0);
-                    let rand2 = rand::thread_rng().gen_range(0.0, 1.0);
+                    let (rand1, rand2) = sample_2d();
 
                     let sample_rec = sample_rectangle_uniform(rand1, rand2, &light.rec);
                     let world_pos = sample_rec.0 * world; 
@@ -106,20 +146,13 @@ pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, curren
                     let v = -dir;
                     let origin = data.position + data.normal * 0.0001;
 
-                    if intersect_plane(origin, l, light.s, -light.direction.vec3_normalize(), light.v1, light.v2, &mut Vector::vec3(0.0, 0.0, 0.0)) {
-                        match trace(origin, l, &scene.scene_objects, &scene.bvh, &scene.object_indices, distance, current_ray_depth + 1, settings, RayType::ShadowRay, stats) {
-                            None => {        
-                                let falloff = distance * distance;
-    
-                                let mut sample_diffuse = Vector::vec3(0.0, 0.0, 0.0);
-                                let mut sample_spec = Vector::vec3(0.0, 0.0, 0.0); 
-    
-                                compute_lighting(data.material.roughness, data.material.specular, n, v, l, falloff, light.intensity(), &mut sample_diffuse, &mut sample_spec);
-    
-                                rec_diffuse += sample_diffuse / sample_rec.1;     
-                            },
-                            _ => ()
-                        }
+                    if intersect_plane(origin, l, light.s, -light.direction.vec3_normalize(), light.v1, light.v2, &mut Vector::vec3(0.0, 0.0, 0.0))
+                        && !occluded(origin, l, scene, 0.0, distance, current_ray_depth + 1, settings, stats) {
+                        let falloff = distance * distance;
+
+                        let (sample_diffuse, _) = compute_lighting(data.material.roughness, data.material.specular, n, v, l, falloff, light.intensity());
+
+                        rec_diffuse += sample_diffuse / sample_rec.1;     
                     }
 
                     let a2 = data.material.roughness * data.material.roughness;
@@ -136,21 +169,18 @@ pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, curren
                     let mut hit = Vector::vec3(0.0, 0.0, 0.0);
                     if intersect_plane(origin, l, light.s, -light.direction.vec3_normalize(), light.v1, light.v2, &mut hit) {
                         let distance = (data.position - hit).vec3_length_f32();
-                        match trace(origin, l, &scene.scene_objects, &scene.bvh, &scene.object_indices, distance, current_ray_depth + 1, settings, RayType::ShadowRay, stats) {
-                            None => {
-                                let light_color = light.intensity();
-
-                                let l_o_h = clamp(l.vec3_dot_f32(h), 0.0, 1.0);
-                                let n_o_h = clamp(n.vec3_dot_f32(h), 0.0, 1.0);
-                    
-                                let f = schlick_fresnel_aprx(l_o_h, data.material.specular);
-                                let d = ggx_distribution(n_o_h, a2);
-                                let g = smith_for_ggx(n_o_l, n_o_v, a2);
-                                let res = f * d * g * light_color * n_o_l;
-                    
-                                rec_spec += res / pdf;
-                            },
-                            _ => ()
+                        if !occluded(origin, l, scene, 0.0, distance, current_ray_depth + 1, settings, stats) {
+                            let light_color = light.intensity();
+
+                            let l_o_h = clamp(l.vec3_dot_f32(h), 0.0, 1.0);
+                            let n_o_h = clamp(n.vec3_dot_f32(h), 0.0, 1.0);
+                
+                            let f = schlick_fresnel_aprx(l_o_h, data.material.specular);
+                            let d = ggx_distribution(n_o_h, a2);
+                            let g = smith_for_ggx(n_o_l, n_o_v, a2);
+                            let res = f * d * g * light_color * n_o_l;
+                
+                            rec_spec += res / pdf;
                         }
                     }
                 } 
@@ -163,14 +193,26 @@ pub fn calculate_color(data: ShadingData, dir: Vector, scene: &SceneData, curren
                 specular += rec_spec;
             }
         }
+
+        if let Some(per_light) = per_light.as_mut() {
+            per_light[i] += diffuse_reflectance * (diffuse - diffuse_before) + (specular - specular_before);
+        }
     }
 
-    let indirect_light = compute_indirect_light(dir, &data, scene, current_ray_depth, settings, ray_type, stats);
-    let color = data.material.albedo / consts::PI * (diffuse + indirect_light.0) + specular + indirect_light.1;
-    color.clamp(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(1.0, 1.0, 1.0))
+    let indirect_light = compute_indirect_light(dir, &data, scene, current_ray_depth, settings, stats);
+
+    LightingComponents {
+        diffuse_reflectance: diffuse_reflectance,
+        direct_diffuse: diffuse,
+        direct_specular: specular,
+        indirect_diffuse: indirect_light.0,
+        indirect_specular: indirect_light.1
+    }
 }
 
-fn compute_lighting(roughness: f32, specular_color: Vector, n: Vector, v: Vector, l: Vector, falloff: f32, light_intensity: Vector, diffuse: &mut Vector, specular: &mut Vector) {
+// the diffuse and specular light a light sends towards v, the diffuse light isn't scaled
+// by the albedo yet
+fn compute_lighting(roughness: f32, specular_color: Vector, n: Vector, v: Vector, l: Vector, falloff: f32, light_intensity: Vector) -> (Vector, Vector) {
     let a2 = roughness * roughness;
 
     let h =  (v + l).vec3_normalize();
@@ -187,22 +229,20 @@ fn compute_lighting(roughness: f32, specular_color: Vector, n: Vector, v: Vector
     //let diffuse_term = Vector::vec3(1.0, 1.0, 1.0) - f;
     let diffuse_term = disney_diffuse_model(n_o_v, n_o_l, n_o_h, roughness);
     let energy = (light_intensity / falloff) * n_o_l;
-    *specular += brdf * energy;
-    *diffuse += energy * diffuse_term;
+    (energy * diffuse_term, brdf * energy)
 }
 
-fn compute_indirect_light(dir: Vector, data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, stats: & mut Stats) -> (Vector, Vector) {
+fn compute_indirect_light(dir: Vector, data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, stats: & mut Stats) -> (Vector, Vector) {
     let mut indirect_diffuse = Vector::vec3(0.0, 0.0, 0.0);
     let mut indirect_specular = Vector::vec3(0.0, 0.0, 0.0);
 
     if current_ray_depth < settings.max_ray_depth {
-        let t;
         let n = data.normal;
-        if n.x().abs() > n.y().abs() {
-            t = Vector::vec3(n.z(), 0.0, -n.x()) / (n.x() * n.x() + n.z() * n.z()).sqrt();
+        let t = if n.x().abs() > n.y().abs() {
+            Vector::vec3(n.z(), 0.0, -n.x()) / (n.x() * n.x() + n.z() * n.z()).sqrt()
         } else {
-            t = Vector::vec3(0.0, -n.z(), n.y()) / (n.y() * n.y() + n.z() * n.z()).sqrt();
-        }
+            Vector::vec3(0.0, -n.z(), n.y()) / (n.y() * n.y() + n.z() * n.z()).sqrt()
+        };
     
         let b = n.vec3_cross(t);
     
@@ -210,14 +250,15 @@ fn compute_indirect_light(dir: Vector, data: &ShadingData, scene: &SceneData, cu
             t, n, b, Vector::vec4(0.0, 0.0, 0.0, 1.0)
         );
 
-        compute_indirect_diffuse(data, scene, current_ray_depth, settings, ray_type, &tbn, &mut indirect_diffuse, stats);
-        compute_indirect_specular(dir, data, scene, current_ray_depth, settings, ray_type, &tbn, &mut indirect_specular, stats);
+        indirect_diffuse = compute_indirect_diffuse(data, scene, current_ray_depth, settings, &tbn, stats);
+        indirect_specular = compute_indirect_specular(dir, data, scene, current_ray_depth, settings, &tbn, stats);
     }
 
     (indirect_diffuse, indirect_specular)
 }
 
-fn compute_indirect_specular(dir: Vector, data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, tbn: &Matrix, specular: & mut Vector, stats: & mut Stats) {
+fn compute_indirect_specular(dir: Vector, data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, tbn: &Matrix, stats: & mut Stats) -> Vector {
+    let mut specular = Vector::vec3(0.0, 0.0, 0.0);
 
     if settings.specular_samples > 0 {
         let mut samples = settings.specular_samples;
@@ -231,10 +272,9 @@ fn compute_indirect_specular(dir: Vector, data: &ShadingData, scene: &SceneData,
         let a2 = data.material.roughness * data.material.roughness;
 
         for _ in 0..samples {
-            let rand1 = rand::thread_rng().gen_range(0.0, 1.0);
-            let rand2 = rand::thread_rng().gen_range(0.0, 1.0);
+            let (rand1, rand2) = sample_2d();
         
-            let (sample, pdf) = importance_sample_ggx(rand1, rand2, a2);
+            let (sample, _) = importance_sample_ggx(rand1, rand2, a2);
         
             let h = (sample * *tbn).vec3_normalize();
 			let l = ((h * 2.0 * v.vec3_dot(h)) - v).vec3_normalize();
@@ -249,7 +289,6 @@ fn compute_indirect_specular(dir: Vector, data: &ShadingData, scene: &SceneData,
             let light_color = cast_ray(data.position + l * 0.0001, l, scene, current_ray_depth + 1, settings, RayType::SpecularRay, stats);
 
             let dot_lh = clamp(l.vec3_dot_f32(h), 0.0, 1.0);
-            let dot_nh = clamp(n.vec3_dot_f32(h), 0.0, 1.0);
 
             let f = schlick_fresnel_aprx(dot_lh, data.material.specular);
             let d = ggx_distribution(n.vec3_dot_f32(h), a2);
@@ -259,14 +298,18 @@ fn compute_indirect_specular(dir: Vector, data: &ShadingData, scene: &SceneData,
             let brdf = (f * d * g) / (4.0 * n.vec3_dot_f32(l).abs() * n.vec3_dot_f32(v).abs());
             let reflectance = (brdf / pdf) * light_color * dot_nl;
 
-            *specular += reflectance;
+            specular += reflectance;
         }
     
-        *specular /= samples as f32;
+        specular /= samples as f32;
     }
+
+    specular
 }
 
-fn compute_indirect_diffuse(data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, ray_type: RayType, tbn: &Matrix, diffuse: & mut Vector, stats: & mut Stats) {
+fn compute_indirect_diffuse(data: &ShadingData, scene: &SceneData, current_ray_depth: u32, settings: RenderSettings, tbn: &Matrix, stats: & mut Stats) -> Vector {
+    let mut diffuse = Vector::vec3(0.0, 0.0, 0.0);
+
     if settings.diffuse_samples > 0 && data.material.metalicness < 1.0 {
         let mut samples = settings.diffuse_samples;
         let n = data.normal;
@@ -276,16 +319,17 @@ fn compute_indirect_diffuse(data: &ShadingData, scene: &SceneData, current_ray_d
         }
 
         for _ in 0..samples {
-            let rand1 = rand::thread_rng().gen_range(0.0, 1.0);
-            let rand2 = rand::thread_rng().gen_range(0.0, 1.0);
+            let (rand1, rand2) = sample_2d();
         
             let sample = sample_hemisphere_cosine_weighted(rand1, rand2);
         
             let dir = (sample.0 * *tbn).vec3_normalize();
             let pdf = sample.1;
-            *diffuse += (cast_ray(data.position + dir * 0.0001, dir, scene, current_ray_depth + 1, settings, RayType::DiffuseRay, stats) / pdf) * clamp(dir.vec3_dot_f32(n), 0.0, 1.0);
+            diffuse += (cast_ray(data.position + dir * 0.0001, dir, scene, current_ray_depth + 1, settings, RayType::DiffuseRay, stats) / pdf) * clamp(dir.vec3_dot_f32(n), 0.0, 1.0);
         }
     
-        *diffuse /= samples as f32;
+        diffuse /= samples as f32;
     }
+
+    diffuse
 }
\ No newline at end of file
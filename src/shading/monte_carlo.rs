@@ -2,17 +2,6 @@ use std::f32::consts;
 use crate::Vector;
 use crate::geometry::Rectangle;
 
-#[inline]
-pub(crate) fn sample_hemisphere_uniform(rand1: f32, rand2:f32) -> (Vector, f32) {
-    let sin_theta = (1.0 - rand1 * rand1).sqrt();
-    let phi = 2.0 * consts::PI * rand2;
-
-    let x = sin_theta * phi.cos();
-    let z = sin_theta * phi.sin();
-    let pdf = 1.0 / (2.0 * consts::PI);
-    (Vector::vec3(x, sin_theta, z), pdf)
-}
-
 #[inline]
 pub(crate) fn sample_hemisphere_cosine_weighted(rand1: f32, rand2:f32) -> (Vector, f32) {
     let sin2_theta  = rand1;
@@ -60,4 +49,42 @@ pub(crate) fn sample_rectangle_uniform(rand1: f32, rand2: f32, rec: &Rectangle)
     let y = rand2 * rec.height - rec.width * 0.5;
 
     (Vector::vec3(x, y, 0.0), rec.area())
-}
\ No newline at end of file
+}
+/// Maps the unit square onto the unit disk keeping the samples evenly spread.
+#[inline]
+pub(crate) fn sample_disk_concentric(rand1: f32, rand2: f32) -> (f32, f32) {
+    let offset_x = 2.0 * rand1 - 1.0;
+    let offset_y = 2.0 * rand2 - 1.0;
+
+    if offset_x == 0.0 && offset_y == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if offset_x.abs() > offset_y.abs() {
+        (offset_x, consts::FRAC_PI_4 * (offset_y / offset_x))
+    } else {
+        (offset_y, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (offset_x / offset_y))
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// Uniformly samples a regular polygon with its corners on the unit circle, the first corner
+/// sits at `rotation` radians. rand1 picks one of the triangles fanning out from the center.
+#[inline]
+pub(crate) fn sample_regular_polygon(rand1: f32, rand2: f32, sides: u32, rotation: f32) -> (f32, f32) {
+    let scaled = rand1 * sides as f32;
+    let side = (scaled as u32).min(sides - 1);
+    let u = scaled - side as f32;
+
+    let angle = 2.0 * consts::PI / sides as f32;
+    let theta_a = rotation + side as f32 * angle;
+    let theta_b = theta_a + angle;
+
+    // uniform point in the triangle center, a, b
+    let su = u.sqrt();
+    let b0 = su * (1.0 - rand2);
+    let b1 = su * rand2;
+
+    (b0 * theta_a.cos() + b1 * theta_b.cos(), b0 * theta_a.sin() + b1 * theta_b.sin())
+}
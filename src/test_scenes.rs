@@ -2,7 +2,6 @@
 #![allow(unused_variables)]
 
 use crate::shading::*;
-use crate::matrix::Matrix;
 use crate::geometry::*;
 use crate::scene::*;
 use crate::vector_simd::*;
@@ -10,9 +9,8 @@ use crate::math::*;
 use crate::bvh::*;
 use crate::camera::Camera;
 use std::f32::consts;
-use std::time::Instant;
 
-pub fn multi_spheres() -> SceneData {
+pub fn multi_spheres(bvh_settings: &BvhSettings) -> SceneData {
     let diffuse = Vector::vec3(0.01, 0.01, 0.01);
     let specular = Vector::vec3(1.0, 0.782, 0.344);
 
@@ -101,24 +99,26 @@ pub fn multi_spheres() -> SceneData {
     let directional_light = lights::Lights::Directional(lights::DirectionalLight::new(Vector::vec3(-0.0, -0.6, -1.0), 1.0, Vector::vec3(1.0, 1.0, 1.0)));
     let lights = vec![directional_light];
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
     let camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
 
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
+    }
 }
 
-pub fn transmission_test() -> SceneData {
+pub fn transmission_test(bvh_settings: &BvhSettings) -> SceneData {
     let sphere = create_scene_object(
         create_sphere(0.4, 40, 20),
         materials::Material::new(Vector::vec3(0.6, 0.6, 0.6), Vector::vec3(0.04, 0.04, 0.04), 0.3, 1.0, 0.0, 0.0),
@@ -148,24 +148,26 @@ pub fn transmission_test() -> SceneData {
     let directional_light = lights::Lights::Directional(lights::DirectionalLight::new(Vector::vec3(-0.4, -0.6, -0.8), 1.0, Vector::vec3(1.0, 1.0, 1.0)));
     let lights = vec![directional_light];
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
     let camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
 
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
+    }
 }
 
-pub fn area_ligt() -> SceneData {
+pub fn area_ligt(bvh_settings: &BvhSettings) -> SceneData {
     let color = Vector::vec3(0.7, 0.7, 0.7);
     let sphere_color = Vector::vec3(0.8, 0.8, 0.8);
 
@@ -250,23 +252,25 @@ pub fn area_ligt() -> SceneData {
     let rec_light = lights::Lights::Rectangular(lights::RectangularLight::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.42, -0.3, -1.4), 1.45, 0.5, 9, 20.0, Vector::vec3(1.0, 1.0, 1.0), 10.0, Vector::vec3(1.0, 1.0, 1.0)));
     let lights = vec![rec_light];
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
     let camera = Camera::new(Vector::vec3(-0.6, 0.25, -1.4), Vector::vec3(0.0, -0.3, -1.4));
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
+    }
 }
 
-pub fn furnance_test() -> SceneData {
+pub fn furnance_test(bvh_settings: &BvhSettings) -> SceneData {
     let sphere = create_scene_object(
         create_sphere(1.0, 40, 20),
         materials::Material::new(Vector::vec3(0.18, 0.18, 0.18), Vector::vec3(0.04, 0.04, 0.04), 0.3, 1.0, 0.0, 0.0),
@@ -280,23 +284,25 @@ pub fn furnance_test() -> SceneData {
     let point_light = lights::Lights::Point(lights::PointLight::new(Vector::vec3(0.3, 1.5, -1.6), 0.0, Vector::vec3(1.0, 0.945, 0.878), 2.0, Vector::vec3(0.0, 0.0, 1.0)));
     let lights = vec![point_light];
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
     let camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene    
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
+    }
 }
 
-pub fn spehres() -> SceneData {
+pub fn spehres(bvh_settings: &BvhSettings) -> SceneData {
     let spec = Vector::vec3(0.04, 0.04, 0.04);
     let white = Vector::vec3(0.8, 0.8, 0.8);
     let black = Vector::vec3(0.0, 0.0, 0.0);
@@ -308,7 +314,7 @@ pub fn spehres() -> SceneData {
     let purple = Vector::vec3(0.51, 0.13, 0.68);
 
 
-    let colors = vec![red, green, orange, white, blue, purple];
+    let colors = [red, green, orange, white, blue, purple];
     let roughness = vec![1.0, 0.65, 0.4, 0.6, 0.7, 0.5, 0.7, 0.35, 0.8, 0.37];
 
     let chrome_spec = Vector::vec3(0.549, 0.556, 0.554);
@@ -371,27 +377,29 @@ pub fn spehres() -> SceneData {
 
     // scene_objects.push(bottom_plane);
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
 
     let directional_light = lights::Lights::Directional(lights::DirectionalLight::new(Vector::vec3(-0.0, -0.6, -1.0), 1.5, Vector::vec3(1.0, 1.0, 1.0)));
     let rec_light = lights::Lights::Rectangular(lights::RectangularLight::new(Vector::vec3(0.0, 1.599, -3.0), Vector::vec3(0.0, -1.0, 0.0), 0.75, 0.75, 10, 5.0, Vector::vec3(1.0, 0.945, 0.878), 10.0, Vector::vec3(1.0, 1.0, 1.0)));
     let lights = vec![directional_light];
     let camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
+    }
 }
 
-pub fn gi_test() -> SceneData {
+pub fn gi_test(bvh_settings: &BvhSettings) -> SceneData {
     let white = Vector::vec3(0.8, 0.8, 0.8);
     let black = Vector::vec3(0.0, 0.0, 0.0);
     let red = Vector::vec3(0.4, 0.15, 0.15);
@@ -475,52 +483,51 @@ pub fn gi_test() -> SceneData {
 
     let scene_objects = vec![cube_green, cube_red, top_plane, bottom_plane, back_plane, wall_plane, right_plane, left_plane];
 
-    let bvh_res = build_bvh(&scene_objects);
+    let bvh_res = build_bvh_with_settings(&scene_objects, bvh_settings);
     let bvh = bvh_res.0;
     let indices = bvh_res.1;
+    let bvh_stats = bvh_res.2;
 
     let point_light = lights::Lights::Point(lights::PointLight::new(Vector::vec3(0.0, 1.099, -3.0), 150.0, Vector::vec3(1.0, 0.945, 0.878), 10.0, Vector::vec3(0.0, 0.0, 1.0)));
     let rec_light = lights::Lights::Rectangular(lights::RectangularLight::new(Vector::vec3(0.0, 1.099, -3.0), Vector::vec3(0.0, 0.0, -3.0), 0.75, 0.75, 10, 5.0, Vector::vec3(1.0, 0.945, 0.878), 10.0, Vector::vec3(1.0, 1.0, 1.0)));
     let lights = vec![point_light];
     let camera = Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0));
 
-    let scene = SceneData {
+    SceneData {
         bvh: bvh,
         object_indices: indices,
         scene_objects: scene_objects,
         lights: lights,
-        camera: camera
-    };
-
-    scene
-}
-
-fn create_scene_object(mesh: Mesh, material: materials::Material, position:Vector, scale: Vector, rotation: Vector) -> SceneObject {
-    let now = Instant::now();
-
-    let scale_matrix = Matrix::scaling_matrix(scale);
-    let translation_matrix = Matrix::translation_matrix(position);
-    let rotation_matrix = Matrix::roatation_x(rotation.x()) * Matrix::roatation_y(rotation.y());
-    let world_matrix = scale_matrix * rotation_matrix * translation_matrix;
-    let inv_world = world_matrix.inverse().transpose();
-
-    let mut transformed_vertices = Vec::new();
-    let mut bounding_box = BoundingBox::new();
-
-    for i in 0..mesh.vertices.len() {
-        let vertex = Vertex::new(mesh.vertices[i].pos * world_matrix, mesh.vertices[i].normal * inv_world);
-        bounding_box.extend_bounds(vertex.pos);   
-        transformed_vertices.push(vertex);
+        camera: camera,
+        bvh_stats: bvh_stats,
+        fingerprint: 0,
+        warnings: Vec::new()
     }
-    
-    let mesh_data = Mesh {
-        vertices: transformed_vertices,
-        indices: mesh.indices,
-        num_tris: mesh.num_tris
-    };
+}
 
-    let end = now.elapsed().as_secs() as f64 + now.elapsed().subsec_nanos() as f64 * 1e-9;
-    // println!("generated scene in {} s", end);
+/// Name, constructor and description of a built in scene. The constructor builds the bvhs
+/// with the settings it is given.
+pub type TestScene = (&'static str, fn(&BvhSettings) -> SceneData, &'static str);
+
+/// Built in scenes that can be selected by name from the command line.
+pub const TEST_SCENES: [TestScene; 6] = [
+    ("spheres", spehres, "metal spheres among randomly colored spheres"),
+    ("multi_spheres", multi_spheres, "spheres with increasing roughness"),
+    ("transmission", transmission_test, "spheres for testing transmission"),
+    ("area_light", area_ligt, "spheres lit by a rectangular area light"),
+    ("furnace", furnance_test, "white furnace test"),
+    ("gi", gi_test, "indirect lighting test")
+];
+
+pub fn find_test_scene(name: &str) -> Option<fn(&BvhSettings) -> SceneData> {
+    TEST_SCENES.iter().find(|scene| scene.0 == name).map(|scene| scene.1)
+}
 
-    SceneObject::new(mesh_data, material, bounding_box)
-}
\ No newline at end of file
+/// Builds the test scene with the given name, its fingerprint is made from the name.
+pub fn load_test_scene(name: &str, bvh_settings: &BvhSettings) -> Option<SceneData> {
+    let mut scene = find_test_scene(name)?(bvh_settings);
+    let mut fingerprint = Fingerprint::new();
+    fingerprint.add(name.as_bytes());
+    scene.fingerprint = fingerprint.finish();
+    Some(scene)
+}
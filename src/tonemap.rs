@@ -0,0 +1,141 @@
+use crate::math::clamp;
+use crate::vector_simd::Vector;
+
+/// Operators that compress the linear radiance into the displayable [0, 1] range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Clips everything above 1.
+    Clamp,
+    /// x / (1 + x) per channel.
+    Reinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    AcesFitted,
+    /// AgX style log encoding with a sigmoid contrast curve, desaturates bright highlights
+    /// instead of skewing their hue.
+    Filmic
+}
+
+impl ToneMapOperator {
+    pub fn from_name(name: &str) -> Option<ToneMapOperator> {
+        match name.to_ascii_lowercase().as_str() {
+            "clamp" | "none" => Some(ToneMapOperator::Clamp),
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "aces" => Some(ToneMapOperator::AcesFitted),
+            "filmic" | "agx" => Some(ToneMapOperator::Filmic),
+            _ => None
+        }
+    }
+}
+
+/// Turns scene radiance into sRGB encoded display values for 8 bit output. High dynamic
+/// range output stores the radiance untouched. Defaults to clamping without an exposure
+/// adjustment.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayTransform {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, every stop doubles the brightness.
+    pub exposure: f32
+}
+
+impl DisplayTransform {
+    pub fn new(operator: ToneMapOperator, exposure: f32) -> Self {
+        Self {
+            operator: operator,
+            exposure: exposure
+        }
+    }
+
+    /// Returns the sRGB encoded color in [0, 1].
+    pub fn apply(&self, color: Vector) -> Vector {
+        let exposed = color * 2.0f32.powf(self.exposure);
+        let (r, g, b, _) = exposed.into();
+        let linear = (non_negative(r), non_negative(g), non_negative(b));
+
+        let (r, g, b) = match self.operator {
+            ToneMapOperator::Clamp => linear,
+            ToneMapOperator::Reinhard => (linear.0 / (1.0 + linear.0), linear.1 / (1.0 + linear.1), linear.2 / (1.0 + linear.2)),
+            ToneMapOperator::AcesFitted => aces_fitted(linear),
+            ToneMapOperator::Filmic => agx(linear)
+        };
+
+        Vector::vec3(srgb_encode(r), srgb_encode(g), srgb_encode(b))
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new(ToneMapOperator::Clamp, 0.0)
+    }
+}
+
+fn non_negative(value: f32) -> f32 {
+    if value > 0.0 {value} else {0.0}
+}
+
+pub(crate) fn srgb_encode(linear: f32) -> f32 {
+    let linear = clamp(linear, 0.0, 1.0);
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn mul_matrix(m: &[[f32; 3]; 3], c: (f32, f32, f32)) -> (f32, f32, f32) {
+    (
+        m[0][0] * c.0 + m[0][1] * c.1 + m[0][2] * c.2,
+        m[1][0] * c.0 + m[1][1] * c.1 + m[1][2] * c.2,
+        m[2][0] * c.0 + m[2][1] * c.1 + m[2][2] * c.2
+    )
+}
+
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777]
+];
+
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602]
+];
+
+fn aces_fitted(color: (f32, f32, f32)) -> (f32, f32, f32) {
+    let rrt_and_odt = |v: f32| (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081);
+
+    let c = mul_matrix(&ACES_INPUT, color);
+    let c = (rrt_and_odt(c.0), rrt_and_odt(c.1), rrt_and_odt(c.2));
+    mul_matrix(&ACES_OUTPUT, c)
+}
+
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.842_479_06, 0.078_433_6, 0.079_223_745],
+    [0.042_328_242, 0.878_468_6, 0.079_166_13],
+    [0.042_375_654, 0.078_433_6, 0.879_143]
+];
+
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.196_879, -0.098_020_88, -0.099_029_74],
+    [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+    [-0.052_971_635, -0.098_043_45, 1.151_073_7]
+];
+
+fn agx(color: (f32, f32, f32)) -> (f32, f32, f32) {
+    const MIN_EV: f32 = -12.473_93;
+    const MAX_EV: f32 = 4.026_069;
+
+    // log2 encode into [0, 1] and apply the polynomial fit of the agx contrast curve
+    let curve = |v: f32| {
+        let v = (clamp(v.max(1e-10).log2(), MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let v2 = v * v;
+        let v4 = v2 * v2;
+        15.5 * v4 * v2 - 40.14 * v4 * v + 31.96 * v4 - 6.868 * v2 * v + 0.4298 * v2 + 0.1191 * v - 0.002_32
+    };
+
+    let c = mul_matrix(&AGX_INSET, color);
+    let c = mul_matrix(&AGX_OUTSET, (curve(c.0), curve(c.1), curve(c.2)));
+
+    // the curve output is display encoded, go back to linear for the srgb encoding
+    (non_negative(c.0).powf(2.2), non_negative(c.1).powf(2.2), non_negative(c.2).powf(2.2))
+}
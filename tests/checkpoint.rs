@@ -0,0 +1,63 @@
+use ray_tracer::{Camera, Lights, Material, RenderSettings, Renderer, SceneBuilder, Vector};
+use ray_tracer::checkpoint::{Checkpoint, CheckpointError};
+use ray_tracer::geometry::create_sphere;
+use ray_tracer::renderer::SnapshotInterval;
+use ray_tracer::shading::lights::DirectionalLight;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// offsets into the file written by `Checkpoint::save`
+const WIDTH_OFFSET: usize = 8;
+const FILM_LAYERS_OFFSET: usize = 88;
+
+fn save_checkpoint(path: &Path) {
+    let scene = SceneBuilder::new()
+        .camera(Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0)))
+        .add_object(create_sphere(1.0, 16, 8), Material::default(), Vector::vec3(0.0, 0.0, -3.0), Vector::vec3(1.0, 1.0, 1.0), Vector::vec3(0.0, 0.0, 0.0))
+        .add_light(Lights::Directional(DirectionalLight::new(Vector::vec3(0.0, -1.0, -1.0), 1.0, Vector::vec3(1.0, 1.0, 1.0))))
+        .build()
+        .unwrap();
+
+    let settings = RenderSettings::new(8, 6, 1, 1, 1, 2, Vector::vec3(0.0, 0.0, 0.0));
+    let interval = SnapshotInterval { passes: Some(1), seconds: None };
+    Renderer::with_threads(settings, 1).render_progressive(&scene, interval, |snapshot| {
+        if snapshot.last {
+            snapshot.checkpoint().save(path).unwrap();
+        }
+    });
+}
+
+fn damaged(name: &str, bytes: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ray_tracer_checkpoint_{}_{}", std::process::id(), name));
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn assert_format_error(path: &Path) {
+    match Checkpoint::load(path) {
+        Err(CheckpointError::Format(_)) => (),
+        Err(error) => panic!("{} gave {} instead of a format error", path.display(), error),
+        Ok(_) => panic!("{} loaded", path.display())
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn damaged_checkpoints_are_rejected() {
+    let path = damaged("valid", &[]);
+    save_checkpoint(&path);
+    let bytes = fs::read(&path).unwrap();
+    assert!(Checkpoint::load(&path).is_ok(), "the undamaged checkpoint doesn't load");
+    fs::remove_file(&path).unwrap();
+
+    let mut huge_image = bytes.clone();
+    huge_image[WIDTH_OFFSET..WIDTH_OFFSET + 8].copy_from_slice(&[0xff; 8]);
+    assert_format_error(&damaged("huge_image", &huge_image));
+
+    let mut film_layers = bytes.clone();
+    film_layers[FILM_LAYERS_OFFSET..FILM_LAYERS_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_format_error(&damaged("film_layers", &film_layers));
+
+    assert_format_error(&damaged("truncated", &bytes[..bytes.len() - 1]));
+}
@@ -0,0 +1,49 @@
+use ray_tracer::{Camera, Lights, Material, RenderBuffers, RenderSettings, Renderer, SceneBuilder, SceneData, Vector};
+use ray_tracer::aov::AovSet;
+use ray_tracer::filter::{FilterType, PixelFilter};
+use ray_tracer::geometry::create_sphere;
+use ray_tracer::shading::lights::DirectionalLight;
+
+const FILTERS: [FilterType; 5] = [FilterType::Box, FilterType::Tent, FilterType::Gaussian, FilterType::Mitchell, FilterType::BlackmanHarris];
+
+fn scene() -> SceneData {
+    let mut builder = SceneBuilder::new()
+        .camera(Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0)))
+        .add_light(Lights::Directional(DirectionalLight::new(Vector::vec3(-0.5, -1.0, -1.0), 2.0, Vector::vec3(1.0, 1.0, 1.0))));
+
+    // small spheres give many edges for the filters to spread across pixels
+    for i in 0..5 {
+        let position = Vector::vec3(i as f32 * 0.45 - 0.9, (i % 2) as f32 * 0.3 - 0.15, -3.0 - i as f32 * 0.2);
+        builder = builder.add_object(create_sphere(0.25, 16, 8), Material::default(), position, Vector::vec3(1.0, 1.0, 1.0), Vector::vec3(0.0, 0.0, 0.0));
+    }
+    builder.add_object(create_sphere(20.0, 32, 16), Material::default(), Vector::vec3(0.0, -20.5, -3.0), Vector::vec3(1.0, 1.0, 1.0), Vector::vec3(0.0, 0.0, 0.0))
+        .build()
+        .unwrap()
+}
+
+fn render(scene: &SceneData, filter: FilterType, threads: usize) -> RenderBuffers {
+    let mut settings = RenderSettings::new(24, 16, 2, 2, 2, 3, Vector::vec3(0.86, 0.92, 1.0));
+    settings.filter = PixelFilter::new(filter);
+    settings.aovs = AovSet::new();
+    settings.aovs.insert_name("albedo");
+    Renderer::with_threads(settings, threads).render(scene).0
+}
+
+fn bits(pixels: &[Vector]) -> Vec<[u32; 3]> {
+    pixels.iter().map(|pixel| [pixel.x().to_bits(), pixel.y().to_bits(), pixel.z().to_bits()]).collect()
+}
+
+#[test]
+fn images_do_not_depend_on_thread_count() {
+    let scene = scene();
+
+    for filter in FILTERS.iter() {
+        let single = render(&scene, *filter, 1);
+        let multiple = render(&scene, *filter, 4);
+
+        assert!(bits(single.beauty.pixels()) == bits(multiple.beauty.pixels()), "{:?} beauty differs between 1 and 4 threads", filter);
+        for ((aov, single), (_, multiple)) in single.aovs.iter().zip(multiple.aovs.iter()) {
+            assert!(bits(single.pixels()) == bits(multiple.pixels()), "{:?} {} aov differs between 1 and 4 threads", filter, aov.name());
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use ray_tracer::{Camera, Lights, Material, RenderSettings, Renderer, SceneBuilder, SceneData, Vector};
+use ray_tracer::aov::{Aov, AovSet};
+use ray_tracer::geometry::create_sphere;
+use ray_tracer::matrix::Matrix;
+use ray_tracer::scene::MeshResource;
+use ray_tracer::shading::lights::DirectionalLight;
+
+use std::sync::Arc;
+
+// a unit sphere instanced with a uniform scale, moved away from the camera by the same
+// scale so it covers the same pixels at any scale
+fn scene(scale: f32) -> SceneData {
+    let resource = Arc::new(MeshResource::new(create_sphere(1.0, 32, 16)));
+    let world = Matrix::scaling_matrix(Vector::vec3(scale, scale, scale)) * Matrix::translation_matrix(Vector::vec3(0.0, 0.0, -3.0 * scale));
+
+    SceneBuilder::new()
+        .camera(Camera::new(Vector::vec3(0.0, 0.0, 0.0), Vector::vec3(0.0, 0.0, -1.0)))
+        .add_instance(&resource, Material::default(), world)
+        .add_light(Lights::Directional(DirectionalLight::new(Vector::vec3(0.0, -1.0, -1.0), 1.0, Vector::vec3(1.0, 1.0, 1.0))))
+        .build()
+        .unwrap()
+}
+
+// depth of every pixel divided by the scale, infinite where the camera ray missed
+fn depths(scale: f32) -> Vec<f32> {
+    let mut settings = RenderSettings::new(32, 32, 0, 0, 0, 1, Vector::vec3(0.0, 0.0, 0.0));
+    settings.aovs = AovSet::new();
+    settings.aovs.insert_name("depth");
+
+    let buffers = Renderer::with_threads(settings, 1).render(&scene(scale)).0;
+    let (_, depth) = buffers.aovs.iter().find(|(aov, _)| *aov == Aov::Depth).unwrap();
+    depth.pixels().iter().map(|pixel| pixel.x() / scale).collect()
+}
+
+#[test]
+fn scaled_instances_are_hit_like_unscaled_ones() {
+    let reference = depths(1.0);
+    assert!(reference.iter().any(|depth| depth.is_finite()), "the unscaled sphere isn't visible");
+
+    for scale in [1.0e-3, 1.0e5].iter() {
+        for (pixel, (depth, expected)) in depths(*scale).iter().zip(reference.iter()).enumerate() {
+            assert_eq!(depth.is_finite(), expected.is_finite(), "pixel {} is hit differently at scale {}", pixel, scale);
+            if expected.is_finite() {
+                assert!((depth - expected).abs() < 1.0e-3 * expected, "pixel {} has depth {} at scale {}, expected {}", pixel, depth, scale, expected);
+            }
+        }
+    }
+}